@@ -0,0 +1,245 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitStr, Path, Type};
+
+/// Derives `Facts` for a struct with named fields, mapping every field to a fact stored
+/// under `<prefix>.<field name>`.
+///
+/// - `#[facts(prefix = "ship")]` on the struct sets the prefix. It defaults to the struct
+///   name in snake case; an empty prefix stores the facts under their bare field names.
+/// - `#[facts(crate = "crate::facts")]` on the struct sets the path of the module that has
+///   `CoolFactStore` and `fact_mapping`, `crate` by default.
+/// - `#[fact(rename = "altitude")]` on a field changes its fact name.
+/// - `#[fact(skip)]` leaves a field out.
+/// - `#[fact(kind = "int")]` picks the fact type, one of `int`, `float`, `bool`, `string` or
+///   `list`. Without it the type is inferred from the field: integers become `int`, `f32` and
+///   `f64` become `float`, `bool` becomes `bool`, `String` becomes `string` and `StringHashSet`
+///   becomes `list`. Any numeric field can be stored as `int` or `float`, and any
+///   `Display + FromStr` field as `string`. Int facts are `i32`, so integer fields that
+///   don't always fit, like `u32`, `i64` or `usize`, have to be stored as `float` or `string`.
+///   Integer fields are only read back from facts that fit them.
+#[proc_macro_derive(Facts, attributes(facts, fact))]
+pub fn derive_facts(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum FactKind {
+    Int,
+    Float,
+    Bool,
+    String,
+    List,
+}
+
+impl FactKind {
+    fn parse(value: &LitStr) -> syn::Result<Self> {
+        match value.value().as_str() {
+            "int" => Ok(FactKind::Int),
+            "float" => Ok(FactKind::Float),
+            "bool" => Ok(FactKind::Bool),
+            "string" => Ok(FactKind::String),
+            "list" => Ok(FactKind::List),
+            _ => Err(syn::Error::new(
+                value.span(),
+                "expected one of \"int\", \"float\", \"bool\", \"string\" or \"list\"",
+            )),
+        }
+    }
+
+    fn infer(ty: &Type) -> Option<Self> {
+        match type_name(ty)?.as_str() {
+            "i8" | "i16" | "i32" | "i64" | "isize" | "u8" | "u16" | "u32" | "u64" | "usize" => {
+                Some(FactKind::Int)
+            }
+            "f32" | "f64" => Some(FactKind::Float),
+            "bool" => Some(FactKind::Bool),
+            "String" => Some(FactKind::String),
+            "StringHashSet" => Some(FactKind::List),
+            _ => None,
+        }
+    }
+}
+
+// The last segment of a type path, e.g. `u8` or `StringHashSet`
+fn type_name(ty: &Type) -> Option<String> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    Some(path.path.segments.last()?.ident.to_string())
+}
+
+// Whether the field is an integer, and if so whether every value fits in an `i32` int fact
+fn integer_fits_i32(ty: &Type) -> Option<bool> {
+    match type_name(ty)?.as_str() {
+        "i8" | "i16" | "i32" | "u8" | "u16" => Some(true),
+        "i64" | "isize" | "u32" | "u64" | "usize" | "i128" | "u128" => Some(false),
+        _ => None,
+    }
+}
+
+struct FactField {
+    ident: Ident,
+    name: String,
+    kind: FactKind,
+    integer: bool,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(input, "Facts can only be derived for structs"));
+    };
+    let Fields::Named(named) = &data.fields else {
+        return Err(syn::Error::new_spanned(input, "Facts needs a struct with named fields"));
+    };
+
+    let mut prefix = to_snake_case(&input.ident.to_string());
+    let mut krate: Path = syn::parse_quote!(crate);
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("facts")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("prefix") {
+                prefix = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else if meta.path.is_ident("crate") {
+                krate = meta.value()?.parse::<LitStr>()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported facts attribute, expected `prefix` or `crate`"))
+            }
+        })?;
+    }
+
+    let mut fields = Vec::new();
+    for field in &named.named {
+        let ident = field.ident.clone().expect("named field");
+        let mut name = ident.to_string();
+        let mut kind = None;
+        let mut skip = false;
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("fact")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    name = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                } else if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else if meta.path.is_ident("kind") {
+                    kind = Some(FactKind::parse(&meta.value()?.parse::<LitStr>()?)?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported fact attribute, expected `rename`, `skip` or `kind`"))
+                }
+            })?;
+        }
+        if skip {
+            continue;
+        }
+        let kind = match kind.or_else(|| FactKind::infer(&field.ty)) {
+            Some(kind) => kind,
+            None => {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    "can't tell which fact type to use, add #[fact(kind = \"...\")] or #[fact(skip)]",
+                ))
+            }
+        };
+        let fits = integer_fits_i32(&field.ty);
+        if matches!(kind, FactKind::Int) && fits == Some(false) {
+            return Err(syn::Error::new_spanned(
+                &field.ty,
+                "doesn't always fit in an int fact, which is an i32; use #[fact(kind = \"float\")], \
+                 #[fact(kind = \"string\")] or #[fact(skip)]",
+            ));
+        }
+        fields.push(FactField { ident, name, kind, integer: fits.is_some() });
+    }
+
+    let writes = fields.iter().map(|field| {
+        let FactField { ident, name, kind, integer } = field;
+        let key = quote! { #krate::fact_mapping::fact_key(prefix, #name) };
+        match kind {
+            // Integer fields are checked to fit above, other numbers are rounded towards zero
+            FactKind::Int if *integer => quote! { store.store_int(#key, i32::from(self.#ident)); },
+            FactKind::Int => quote! { store.store_int(#key, self.#ident as i32); },
+            FactKind::Float => quote! { store.store_float(#key, self.#ident as f32); },
+            FactKind::Bool => quote! { store.store_bool(#key, self.#ident); },
+            FactKind::String => quote! { store.store_string(#key, self.#ident.to_string()); },
+            FactKind::List => quote! { store.store_list(#key, self.#ident.clone()); },
+        }
+    });
+
+    let reads = fields.iter().map(|field| {
+        let FactField { ident, name, kind, integer } = field;
+        let key = quote! { &#krate::fact_mapping::fact_key(prefix, #name) };
+        match kind {
+            FactKind::Int if *integer => quote! {
+                if let Some(Ok(value)) = store.get_int(#key).map(|value| (*value).try_into()) {
+                    self.#ident = value;
+                }
+            },
+            FactKind::Int => quote! {
+                if let Some(value) = store.get_int(#key) {
+                    self.#ident = *value as _;
+                }
+            },
+            FactKind::Float => quote! {
+                if let Some(value) = store.get_float(#key) {
+                    self.#ident = *value as _;
+                }
+            },
+            FactKind::Bool => quote! {
+                if let Some(value) = store.get_bool(#key) {
+                    self.#ident = *value;
+                }
+            },
+            FactKind::String => quote! {
+                if let Some(Ok(value)) = store.get_string(#key).map(|value| value.parse()) {
+                    self.#ident = value;
+                }
+            },
+            FactKind::List => quote! {
+                if let Some(value) = store.get_list(#key) {
+                    self.#ident = value.clone();
+                }
+            },
+        }
+    });
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #krate::fact_mapping::Facts for #ident #ty_generics #where_clause {
+            fn fact_prefix() -> &'static str {
+                #prefix
+            }
+
+            fn write_facts_under(&self, store: &mut #krate::CoolFactStore, prefix: &str) {
+                #(#writes)*
+            }
+
+            fn read_facts_under(&mut self, store: &#krate::CoolFactStore, prefix: &str) {
+                #(#reads)*
+            }
+        }
+    })
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
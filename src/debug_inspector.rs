@@ -0,0 +1,405 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bevy::input::mouse::MouseWheel;
+use bevy::prelude::*;
+use bevy::utils::hashbrown::HashMap;
+
+use crate::entity_facts::{FactStore, LayeredFacts};
+use crate::{CoolFactStore, Fact, FactRemoved, FactUpdated, RuleEngine, RuleUpdated, StoryEngine};
+
+// The oldest lines are dropped once the log holds this many
+pub const EVENT_LOG_CAPACITY: usize = 200;
+// Lines of the log shown at once
+const EVENT_LOG_LINES: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FactSortColumn {
+    #[default]
+    Key,
+    Kind,
+    Value,
+}
+
+impl FactSortColumn {
+    fn next(self) -> Self {
+        match self {
+            FactSortColumn::Key => FactSortColumn::Kind,
+            FactSortColumn::Kind => FactSortColumn::Value,
+            FactSortColumn::Value => FactSortColumn::Key,
+        }
+    }
+}
+
+/// State of the debug overlay, toggled with F3. While it is open `/` edits the fact filter
+/// (Enter to finish), F4 changes the sort column, F5 flips the sort order, F6 picks the rule
+/// to explain and PageUp, PageDown or the mouse wheel scroll the event log.
+#[derive(Resource, Default)]
+pub struct DebugInspector {
+    visible: bool,
+    filter: String,
+    editing_filter: bool,
+    sort_column: FactSortColumn,
+    descending: bool,
+    rule_changed_at: HashMap<String, Duration>,
+    explained_rule: Option<String>,
+    event_log: VecDeque<String>,
+    // Lines scrolled back from the newest one
+    log_scroll: usize,
+}
+
+impl DebugInspector {
+    // Append a line to the event log, keeping the view in place if it is scrolled back
+    pub fn log(&mut self, line: String) {
+        self.event_log.push_back(line);
+        if self.event_log.len() > EVENT_LOG_CAPACITY {
+            self.event_log.pop_front();
+        }
+        if self.log_scroll > 0 {
+            self.scroll_log(1);
+        }
+    }
+
+    // Remember when a rule changed state, `rule` includes the entity for per-entity rules
+    pub fn rule_changed(&mut self, rule: String, at: Duration) {
+        self.rule_changed_at.insert(rule, at);
+    }
+
+    // Explain the next rule by name, or none after the last one
+    fn explain_next_rule(&mut self, rules: &RuleEngine) {
+        let mut names: Vec<&String> = rules.rules.keys().collect();
+        names.sort();
+        let next = match &self.explained_rule {
+            Some(current) => names.into_iter().find(|name| *name > current),
+            None => names.into_iter().next(),
+        };
+        self.explained_rule = next.cloned();
+    }
+
+    fn scroll_log(&mut self, lines: isize) {
+        let max_scroll = self.event_log.len().saturating_sub(EVENT_LOG_LINES);
+        self.log_scroll = self.log_scroll.saturating_add_signed(lines).min(max_scroll);
+    }
+
+    fn visible_log(&self) -> impl Iterator<Item = &String> {
+        let end = self.event_log.len() - self.log_scroll;
+        let start = end.saturating_sub(EVENT_LOG_LINES);
+        self.event_log.range(start..end)
+    }
+
+    fn matches_filter(&self, text: &str) -> bool {
+        text.to_lowercase().contains(&self.filter.to_lowercase())
+    }
+}
+
+/// The root node of the overlay
+#[derive(Component)]
+pub struct DebugOverlay;
+
+#[derive(Component, Clone, Copy)]
+pub enum InspectorSection {
+    Facts,
+    Rules,
+    Stories,
+    EventLog,
+}
+
+pub fn spawn_debug_overlay(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                visibility: Visibility::Hidden,
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(0.0),
+                    width: Val::Percent(40.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(8.0),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    overflow: Overflow::clip(),
+                    ..default()
+                },
+                background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.85)),
+                z_index: ZIndex::Global(20),
+                ..default()
+            },
+            DebugOverlay,
+        ))
+        .with_children(|builder| {
+            for section in [
+                InspectorSection::Facts,
+                InspectorSection::Rules,
+                InspectorSection::Stories,
+                InspectorSection::EventLog,
+            ] {
+                builder.spawn((
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 14.0,
+                            color: Color::WHITE,
+                        },
+                    ),
+                    section,
+                ));
+            }
+        });
+}
+
+pub fn debug_inspector_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut wheel: EventReader<MouseWheel>,
+    mut inspector: ResMut<DebugInspector>,
+    rules: Res<RuleEngine>,
+) {
+    if keys.just_pressed(KeyCode::F3) {
+        inspector.visible = !inspector.visible;
+        inspector.editing_filter = false;
+    }
+    if !inspector.visible {
+        characters.clear();
+        wheel.clear();
+        return;
+    }
+
+    if inspector.editing_filter {
+        for character in characters.read() {
+            inspector.filter.extend(character.char.chars().filter(|c| !c.is_control()));
+        }
+        if keys.just_pressed(KeyCode::Backspace) {
+            inspector.filter.pop();
+        }
+        if keys.just_pressed(KeyCode::Enter) {
+            inspector.editing_filter = false;
+        }
+        return;
+    }
+    characters.clear();
+
+    if keys.just_pressed(KeyCode::Slash) {
+        inspector.editing_filter = true;
+    }
+    if keys.just_pressed(KeyCode::F4) {
+        inspector.sort_column = inspector.sort_column.next();
+    }
+    if keys.just_pressed(KeyCode::F5) {
+        inspector.descending = !inspector.descending;
+    }
+    if keys.just_pressed(KeyCode::F6) {
+        inspector.explain_next_rule(&rules);
+    }
+    if keys.just_pressed(KeyCode::PageUp) {
+        inspector.scroll_log(EVENT_LOG_LINES as isize);
+    }
+    if keys.just_pressed(KeyCode::PageDown) {
+        inspector.scroll_log(-(EVENT_LOG_LINES as isize));
+    }
+    for event in wheel.read() {
+        inspector.scroll_log(event.y.signum() as isize);
+    }
+}
+
+// One row of the fact table, `scope` is `global` or the entity owning the store
+struct FactRow {
+    scope: String,
+    key: String,
+    kind: String,
+    value: String,
+}
+
+impl FactRow {
+    fn new(scope: String, fact: &Fact) -> Self {
+        FactRow {
+            scope,
+            key: fact.key().to_string(),
+            kind: format!("{:?}", fact.kind()),
+            value: fact.value_string(),
+        }
+    }
+}
+
+fn facts_section(inspector: &DebugInspector, storage: &CoolFactStore, stores: &Query<(Entity, &FactStore)>) -> String {
+    let mut rows: Vec<FactRow> = storage
+        .facts
+        .values()
+        .map(|fact| FactRow::new("global".to_string(), fact))
+        .collect();
+    for (entity, store) in stores.iter() {
+        rows.extend(store.facts.values().map(|fact| FactRow::new(format!("{:?}", entity), fact)));
+    }
+    rows.retain(|row| inspector.matches_filter(&row.key) || inspector.matches_filter(&row.value));
+    rows.sort_by(|a, b| {
+        let ordering = match inspector.sort_column {
+            FactSortColumn::Key => a.key.cmp(&b.key),
+            FactSortColumn::Kind => a.kind.cmp(&b.kind).then_with(|| a.key.cmp(&b.key)),
+            FactSortColumn::Value => a.value.cmp(&b.value).then_with(|| a.key.cmp(&b.key)),
+        };
+        ordering.then_with(|| a.scope.cmp(&b.scope))
+    });
+    if inspector.descending {
+        rows.reverse();
+    }
+
+    let mut text = format!(
+        "FACTS  sort: {:?} {}  filter: {}{}\n",
+        inspector.sort_column,
+        if inspector.descending { "desc" } else { "asc" },
+        inspector.filter,
+        if inspector.editing_filter { "_" } else { "" },
+    );
+    for row in rows {
+        text.push_str(&format!("{} [{}] {} = {}\n", row.key, row.scope, row.kind, row.value));
+    }
+    text
+}
+
+fn rules_section(
+    inspector: &DebugInspector,
+    rules: &RuleEngine,
+    storage: &CoolFactStore,
+    stores: &Query<(Entity, &FactStore)>,
+) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let last_changed = |name: &str| match inspector.rule_changed_at.get(name) {
+        Some(at) => format!("changed at {:.1}s", at.as_secs_f32()),
+        None => "never changed".to_string(),
+    };
+    for (name, rule) in &rules.rules {
+        if rule.per_entity {
+            for (entity, states) in &rules.entity_rule_states {
+                let state = states.get(name).copied().unwrap_or(false);
+                let key = format!("{} ({:?})", name, entity);
+                lines.push(format!("{}: {}, {}", key, state, last_changed(&key)));
+            }
+        } else {
+            let fired = match rules.firing(name) {
+                Some(firing) => format!(", fired {} times", firing.fires),
+                None => String::new(),
+            };
+            lines.push(format!("{}: {}, {}{}", name, rules.is_active(name), last_changed(name), fired));
+        }
+    }
+    lines.sort();
+    let mut text = format!("RULES\n{}", lines.join("\n"));
+
+    let Some(name) = &inspector.explained_rule else {
+        return text;
+    };
+    let per_entity = rules.rules.get(name).is_some_and(|rule| rule.per_entity);
+    let explanations = if per_entity {
+        stores
+            .iter()
+            .filter_map(|(entity, store)| {
+                let explanation = rules.explain(name, &LayeredFacts::new(&store.0, storage))?;
+                Some(format!("{:?}\n{}", entity, explanation.render()))
+            })
+            .collect()
+    } else {
+        rules.explain(name, storage).map(|explanation| explanation.render()).into_iter().collect::<Vec<_>>()
+    };
+    text.push_str(&format!("\n\nWHY {}\n{}", name, explanations.join("\n")));
+    text
+}
+
+fn stories_section(stories: &StoryEngine) -> String {
+    let lines: Vec<String> = stories
+        .stories
+        .iter()
+        .map(|story| match story.beats.get(story.active_beat_index) {
+            Some(beat) => format!(
+                "{}: {} ({}/{})",
+                story.name,
+                beat.name,
+                story.active_beat_index + 1,
+                story.beats.len()
+            ),
+            None => format!("{}: finished", story.name),
+        })
+        .collect();
+    format!("STORIES\n{}", lines.join("\n"))
+}
+
+fn event_log_section(inspector: &DebugInspector) -> String {
+    let lines: Vec<&str> = inspector.visible_log().map(|line| line.as_str()).collect();
+    format!(
+        "EVENTS ({}/{}, {} back)\n{}",
+        inspector.event_log.len(),
+        EVENT_LOG_CAPACITY,
+        inspector.log_scroll,
+        lines.join("\n")
+    )
+}
+
+pub fn render_debug_overlay(
+    inspector: Res<DebugInspector>,
+    storage: Res<CoolFactStore>,
+    stores: Query<(Entity, &FactStore)>,
+    rules: Res<RuleEngine>,
+    stories: Res<StoryEngine>,
+    mut overlay: Query<&mut Visibility, With<DebugOverlay>>,
+    mut sections: Query<(&InspectorSection, &mut Text)>,
+) {
+    for mut visibility in overlay.iter_mut() {
+        *visibility = if inspector.visible { Visibility::Visible } else { Visibility::Hidden };
+    }
+    if !inspector.visible {
+        return;
+    }
+    for (section, mut text) in sections.iter_mut() {
+        text.sections[0].value = match section {
+            InspectorSection::Facts => facts_section(&inspector, &storage, &stores),
+            InspectorSection::Rules => rules_section(&inspector, &rules, &storage, &stores),
+            InspectorSection::Stories => stories_section(&stories),
+            InspectorSection::EventLog => event_log_section(&inspector),
+        };
+    }
+}
+
+pub fn log_fact_events(
+    mut inspector: ResMut<DebugInspector>,
+    mut updated: EventReader<FactUpdated>,
+    mut removed: EventReader<FactRemoved>,
+) {
+    for event in updated.read() {
+        let line = match event.entity {
+            Some(entity) => format!("{:?} ({:?})", event.fact, entity),
+            None => format!("{:?}", event.fact),
+        };
+        inspector.log(line);
+    }
+    for event in removed.read() {
+        let line = match event.entity {
+            Some(entity) => format!("Removed {} ({:?})", event.key, entity),
+            None => format!("Removed {}", event.key),
+        };
+        inspector.log(line);
+    }
+}
+
+pub fn log_rule_events(
+    mut inspector: ResMut<DebugInspector>,
+    mut rule_updated: EventReader<RuleUpdated>,
+    time: Res<Time>,
+) {
+    for event in rule_updated.read() {
+        let key = match (event.entity, event.bindings.as_slice()) {
+            (_, [_, ..]) => {
+                let bindings: Vec<String> =
+                    event.bindings.iter().map(|(variable, entity)| format!("{} = {:?}", variable, entity)).collect();
+                format!("{} ({})", event.rule, bindings.join(", "))
+            }
+            (Some(entity), []) => format!("{} ({:?})", event.rule, entity),
+            (None, []) => event.rule.clone(),
+        };
+        let cause = match event.triggered_by.as_slice() {
+            [] => String::new(),
+            facts => format!(" by {}", facts.join(", ")),
+        };
+        inspector.log(format!("Rule {} -> {}{} (frame {})", key, event.new_state, cause, event.frame));
+        inspector.rule_changed(key, time.elapsed());
+    }
+}
@@ -0,0 +1,708 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+
+use bevy::prelude::*;
+
+use crate::fact_batch::FactError;
+use crate::game_state::LoadLevel;
+use crate::{CoolFactStore, Fact, FactKind, FactUpdated, FloatValue, RuleEngine, StoryEngine, StringHashSet};
+
+// The oldest output lines are dropped once the console holds this many
+const CONSOLE_OUTPUT_CAPACITY: usize = 100;
+// Output lines shown above the input line
+const CONSOLE_OUTPUT_LINES: usize = 12;
+
+const COMMANDS: [&str; 14] = [
+    "set", "get", "add", "list", "watch", "unwatch", "enable", "disable", "group", "remove", "explain", "beat", "load",
+    "help",
+];
+
+const HELP: &str = "set <key> <value> | get <key> | add <key> <amount> | list [namespace] | \
+watch [key] | unwatch <key> | enable <rule> | disable <rule> | group <group> on|off | remove <rule> | \
+explain <rule> | beat <story> <beat> | load <level>";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsoleCommand {
+    // The value is parsed when the command runs, as the type the fact already has
+    Set { key: String, value: String },
+    Get { key: String },
+    Add { key: String, amount: String },
+    List { namespace: Option<String> },
+    Watch { key: Option<String> },
+    Unwatch { key: String },
+    SetRuleEnabled { rule: String, enabled: bool },
+    SetGroupEnabled { group: String, enabled: bool },
+    RemoveRule { rule: String },
+    Explain { rule: String },
+    JumpToBeat { story: String, beat: String },
+    LoadLevel { name: String },
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsoleError {
+    Empty,
+    UnknownCommand(String),
+    Usage(&'static str),
+    NotANumber(String),
+    // A value that can't be read as the type of the fact it is for
+    WrongType { value: String, kind: FactKind },
+    Overflow(String),
+    Fact(FactError),
+    UnknownFact(String),
+    NotNumeric(String),
+    UnknownRule(String),
+    UnknownGroup(String),
+    UnknownStory(String),
+    UnknownBeat { story: String, beat: String },
+}
+
+impl fmt::Display for ConsoleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsoleError::Empty => write!(f, "no command given"),
+            ConsoleError::UnknownCommand(command) => write!(f, "unknown command {}, try help", command),
+            ConsoleError::Usage(usage) => write!(f, "usage: {}", usage),
+            ConsoleError::NotANumber(value) => write!(f, "{} is not a number", value),
+            ConsoleError::WrongType { value, kind } => write!(f, "{} is not a {:?}", value, kind),
+            ConsoleError::Overflow(key) => write!(f, "{} would overflow", key),
+            ConsoleError::Fact(error) => write!(f, "{}", error),
+            ConsoleError::UnknownFact(key) => write!(f, "no fact {}", key),
+            ConsoleError::NotNumeric(key) => write!(f, "fact {} is not an int or float", key),
+            ConsoleError::UnknownRule(rule) => write!(f, "no rule {}", rule),
+            ConsoleError::UnknownGroup(group) => write!(f, "no rule is in group {}", group),
+            ConsoleError::UnknownStory(story) => write!(f, "no story {}", story),
+            ConsoleError::UnknownBeat { story, beat } => write!(f, "story {} has no beat {}", story, beat),
+        }
+    }
+}
+
+/// What a command did. Loading a level has to go through the app, so it is handed back
+/// instead of being done by `execute`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsoleReply {
+    Text(String),
+    LoadLevel(String),
+}
+
+// Turn a typed value into a fact: ints, floats, `true`/`false`, `[a, b]` lists, and
+// anything else, with or without quotes, as a string
+pub fn parse_value(key: &str, value: &str) -> Fact {
+    let key = key.to_string();
+    let value = value.trim();
+    if let Ok(value) = value.parse::<i32>() {
+        Fact::Int(key, value)
+    } else if let Ok(value) = value.parse::<f32>() {
+        Fact::Float(key, FloatValue(value))
+    } else if let Ok(value) = value.parse::<bool>() {
+        Fact::Bool(key, value)
+    } else if let Some(items) = value.strip_prefix('[').and_then(|value| value.strip_suffix(']')) {
+        let mut list = StringHashSet::new();
+        for item in items.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            list.insert(item.to_string());
+        }
+        Fact::StringList(key, list)
+    } else {
+        Fact::String(key, value.trim_matches('"').to_string())
+    }
+}
+
+// Read a value as the given type of fact. Ints are taken for floats, and a list can be
+// written with or without brackets.
+pub fn parse_value_as(key: &str, value: &str, kind: FactKind) -> Result<Fact, ConsoleError> {
+    let key = key.to_string();
+    let value = value.trim();
+    let wrong_type = || ConsoleError::WrongType { value: value.to_string(), kind };
+    Ok(match kind {
+        FactKind::Int => Fact::Int(key, value.parse().map_err(|_| wrong_type())?),
+        FactKind::Float => Fact::Float(key, FloatValue(value.parse().map_err(|_| wrong_type())?)),
+        FactKind::Bool => Fact::Bool(key, value.parse().map_err(|_| wrong_type())?),
+        FactKind::String => Fact::String(key, value.trim_matches('"').to_string()),
+        FactKind::StringList => {
+            let items = value.strip_prefix('[').and_then(|value| value.strip_suffix(']')).unwrap_or(value);
+            let list = items.split(',').map(str::trim).filter(|item| !item.is_empty()).collect();
+            Fact::StringList(key, list)
+        }
+    })
+}
+
+// The type a fact has, or was declared with if it isn't set
+fn kind_of(storage: &CoolFactStore, key: &str) -> Option<FactKind> {
+    storage.facts.get(key).or_else(|| storage.defaults.get(key)).map(Fact::kind)
+}
+
+pub fn parse_command(line: &str) -> Result<ConsoleCommand, ConsoleError> {
+    let mut words = line.split_whitespace();
+    let command = words.next().ok_or(ConsoleError::Empty)?;
+    let args: Vec<&str> = words.collect();
+    match (command, args.as_slice()) {
+        ("set", [key, value @ ..]) if !value.is_empty() => Ok(ConsoleCommand::Set {
+            key: key.to_string(),
+            value: value.join(" "),
+        }),
+        ("set", _) => Err(ConsoleError::Usage("set <key> <value>")),
+        ("get", [key]) => Ok(ConsoleCommand::Get { key: key.to_string() }),
+        ("get", _) => Err(ConsoleError::Usage("get <key>")),
+        ("add", [key, amount]) => match parse_value(key, amount) {
+            Fact::Int(..) | Fact::Float(..) => {
+                Ok(ConsoleCommand::Add { key: key.to_string(), amount: amount.to_string() })
+            }
+            _ => Err(ConsoleError::NotANumber(amount.to_string())),
+        },
+        ("add", _) => Err(ConsoleError::Usage("add <key> <amount>")),
+        ("list", []) => Ok(ConsoleCommand::List { namespace: None }),
+        ("list", [namespace]) => Ok(ConsoleCommand::List { namespace: Some(namespace.to_string()) }),
+        ("list", _) => Err(ConsoleError::Usage("list [namespace]")),
+        ("watch", []) => Ok(ConsoleCommand::Watch { key: None }),
+        ("watch", [key]) => Ok(ConsoleCommand::Watch { key: Some(key.to_string()) }),
+        ("watch", _) => Err(ConsoleError::Usage("watch [key]")),
+        ("unwatch", [key]) => Ok(ConsoleCommand::Unwatch { key: key.to_string() }),
+        ("unwatch", _) => Err(ConsoleError::Usage("unwatch <key>")),
+        ("enable", [rule]) => Ok(ConsoleCommand::SetRuleEnabled { rule: rule.to_string(), enabled: true }),
+        ("enable", _) => Err(ConsoleError::Usage("enable <rule>")),
+        ("disable", [rule]) => Ok(ConsoleCommand::SetRuleEnabled { rule: rule.to_string(), enabled: false }),
+        ("disable", _) => Err(ConsoleError::Usage("disable <rule>")),
+        ("group", [group, "on"]) => Ok(ConsoleCommand::SetGroupEnabled { group: group.to_string(), enabled: true }),
+        ("group", [group, "off"]) => Ok(ConsoleCommand::SetGroupEnabled { group: group.to_string(), enabled: false }),
+        ("group", _) => Err(ConsoleError::Usage("group <group> on|off")),
+        ("remove", [rule]) => Ok(ConsoleCommand::RemoveRule { rule: rule.to_string() }),
+        ("remove", _) => Err(ConsoleError::Usage("remove <rule>")),
+        ("explain", [rule]) => Ok(ConsoleCommand::Explain { rule: rule.to_string() }),
+        ("explain", _) => Err(ConsoleError::Usage("explain <rule>")),
+        ("beat", [story, beat]) => Ok(ConsoleCommand::JumpToBeat {
+            story: story.to_string(),
+            beat: beat.to_string(),
+        }),
+        ("beat", _) => Err(ConsoleError::Usage("beat <story> <beat>")),
+        ("load", [name]) => Ok(ConsoleCommand::LoadLevel { name: name.to_string() }),
+        ("load", _) => Err(ConsoleError::Usage("load <level>")),
+        ("help", _) => Ok(ConsoleCommand::Help),
+        (command, _) => Err(ConsoleError::UnknownCommand(command.to_string())),
+    }
+}
+
+pub fn execute(
+    command: ConsoleCommand,
+    storage: &mut CoolFactStore,
+    rules: &mut RuleEngine,
+    stories: &mut StoryEngine,
+    watches: &mut BTreeSet<String>,
+) -> Result<ConsoleReply, ConsoleError> {
+    let text = match command {
+        ConsoleCommand::Set { key, value } => {
+            let fact = match kind_of(storage, &key) {
+                Some(kind) => parse_value_as(&key, &value, kind)?,
+                None => parse_value(&key, &value),
+            };
+            let text = format!("{} = {}", key, fact.value_string());
+            storage.transaction(|batch| {
+                batch.store_fact(fact, None);
+            })
+            .map_err(ConsoleError::Fact)?;
+            text
+        }
+        ConsoleCommand::Get { key } => match storage.facts.get(&key) {
+            Some(fact) => format!("{} = {} ({:?})", key, fact.value_string(), fact.kind()),
+            None => return Err(ConsoleError::UnknownFact(key)),
+        },
+        ConsoleCommand::Add { key, amount } => {
+            // A declared fact that isn't set counts as zero
+            let current = storage.facts.get(&key);
+            let sum = match kind_of(storage, &key) {
+                None => parse_value(&key, &amount),
+                Some(FactKind::Int) => {
+                    let wrong_type = || ConsoleError::WrongType { value: amount.clone(), kind: FactKind::Int };
+                    let amount: i32 = amount.parse().map_err(|_| wrong_type())?;
+                    let current = match current {
+                        Some(Fact::Int(_, current)) => *current,
+                        _ => 0,
+                    };
+                    let sum = current.checked_add(amount).ok_or_else(|| ConsoleError::Overflow(key.clone()))?;
+                    Fact::Int(key.clone(), sum)
+                }
+                Some(FactKind::Float) => {
+                    let current = current.and_then(Fact::as_f32).unwrap_or(0.0);
+                    let amount: f32 = amount.parse().map_err(|_| ConsoleError::NotANumber(amount.clone()))?;
+                    Fact::Float(key.clone(), FloatValue(current + amount))
+                }
+                Some(_) => return Err(ConsoleError::NotNumeric(key)),
+            };
+            let text = format!("{} = {}", key, sum.value_string());
+            storage.transaction(|batch| {
+                batch.store_fact(sum, None);
+            })
+            .map_err(ConsoleError::Fact)?;
+            text
+        }
+        ConsoleCommand::List { namespace } => {
+            let lines: Vec<String> = match &namespace {
+                Some(namespace) => storage.facts_in(namespace).map(describe_fact).collect(),
+                None => storage.index.iter().filter_map(|key| storage.facts.get(key)).map(describe_fact).collect(),
+            };
+            if lines.is_empty() {
+                "no facts".to_string()
+            } else {
+                lines.join("\n")
+            }
+        }
+        ConsoleCommand::Watch { key: Some(key) } => {
+            watches.insert(key.clone());
+            format!("watching {}", key)
+        }
+        ConsoleCommand::Watch { key: None } => {
+            if watches.is_empty() {
+                "not watching any facts".to_string()
+            } else {
+                watches.iter().cloned().collect::<Vec<_>>().join(", ")
+            }
+        }
+        ConsoleCommand::Unwatch { key } => {
+            watches.remove(&key);
+            format!("stopped watching {}", key)
+        }
+        ConsoleCommand::SetRuleEnabled { rule, enabled } => {
+            if !rules.set_enabled(&rule, enabled) {
+                return Err(ConsoleError::UnknownRule(rule));
+            }
+            format!("{} {}", if enabled { "enabled" } else { "disabled" }, rule)
+        }
+        ConsoleCommand::SetGroupEnabled { group, enabled } => {
+            if !rules.set_group_enabled(&group, enabled) {
+                return Err(ConsoleError::UnknownGroup(group));
+            }
+            format!("{} group {}", if enabled { "enabled" } else { "disabled" }, group)
+        }
+        ConsoleCommand::RemoveRule { rule } => match rules.remove_rule(&rule) {
+            Some(_) => format!("removed {}", rule),
+            None => return Err(ConsoleError::UnknownRule(rule)),
+        },
+        // Per-entity rules are explained against the global facts only
+        ConsoleCommand::Explain { rule } => match rules.explain(&rule, &*storage) {
+            Some(explanation) => explanation.render(),
+            None => return Err(ConsoleError::UnknownRule(rule)),
+        },
+        ConsoleCommand::JumpToBeat { story, beat } => {
+            let Some(found) = stories.stories.iter_mut().find(|s| s.name == story) else {
+                return Err(ConsoleError::UnknownStory(story));
+            };
+            if !found.jump_to_beat(&beat) {
+                return Err(ConsoleError::UnknownBeat { story, beat });
+            }
+            format!("{} is at beat {}", story, beat)
+        }
+        ConsoleCommand::LoadLevel { name } => return Ok(ConsoleReply::LoadLevel(name)),
+        ConsoleCommand::Help => HELP.to_string(),
+    };
+    Ok(ConsoleReply::Text(text))
+}
+
+fn describe_fact(fact: &Fact) -> String {
+    format!("{} = {}", fact.key(), fact.value_string())
+}
+
+// Complete the last word of the line: the command name first, then rule names for
+// enable/disable/remove, group names and on/off for group, story and beat names for beat, and
+// fact keys for everything else.
+// Returns the completed line and every candidate when more than one matches.
+pub fn complete(
+    line: &str,
+    storage: &CoolFactStore,
+    rules: &RuleEngine,
+    stories: &StoryEngine,
+) -> (String, Vec<String>) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (head, partial) = if line.ends_with(char::is_whitespace) || words.is_empty() {
+        (words.as_slice(), "")
+    } else {
+        (&words[..words.len() - 1], words[words.len() - 1])
+    };
+
+    let candidates: Vec<String> = match head {
+        [] => COMMANDS.iter().map(|command| command.to_string()).collect(),
+        ["enable" | "disable" | "remove" | "explain"] => rules.rules.keys().cloned().collect(),
+        ["group"] => rules.groups().into_iter().map(str::to_string).collect(),
+        ["group", _] => vec!["on".to_string(), "off".to_string()],
+        ["beat"] => stories.stories.iter().map(|story| story.name.clone()).collect(),
+        ["beat", story] => stories
+            .stories
+            .iter()
+            .filter(|s| s.name == *story)
+            .flat_map(|s| s.beats.iter().map(|beat| beat.name.clone()))
+            .collect(),
+        [_] => storage.index.iter().cloned().collect(),
+        _ => Vec::new(),
+    };
+    let mut matches: Vec<String> = candidates.into_iter().filter(|c| c.starts_with(partial)).collect();
+    matches.sort();
+    matches.dedup();
+
+    let completed = match matches.as_slice() {
+        [] => partial.to_string(),
+        [only] => format!("{} ", only),
+        [first, rest @ ..] => rest.iter().fold(first.clone(), |prefix, candidate| {
+            prefix
+                .chars()
+                .zip(candidate.chars())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect()
+        }),
+    };
+    let mut line: String = head.iter().map(|word| format!("{} ", word)).collect();
+    line.push_str(&completed);
+    let candidates = if matches.len() > 1 { matches } else { Vec::new() };
+    (line, candidates)
+}
+
+/// The drop-down console, toggled with the backtick key. Up and Down walk the command
+/// history, Tab completes.
+#[derive(Resource, Default)]
+pub struct DevConsole {
+    open: bool,
+    input: String,
+    history: Vec<String>,
+    // Position while walking the history, `None` when editing a new line
+    history_index: Option<usize>,
+    output: VecDeque<String>,
+    watches: BTreeSet<String>,
+}
+
+impl DevConsole {
+    pub fn print(&mut self, text: &str) {
+        for line in text.lines() {
+            self.output.push_back(line.to_string());
+            if self.output.len() > CONSOLE_OUTPUT_CAPACITY {
+                self.output.pop_front();
+            }
+        }
+    }
+
+    fn recall(&mut self, older: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+        let index = match (self.history_index, older) {
+            (None, true) => Some(self.history.len() - 1),
+            (None, false) => None,
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) if index + 1 < self.history.len() => Some(index + 1),
+            (Some(_), false) => None,
+        };
+        self.history_index = index;
+        self.input = index.map(|index| self.history[index].clone()).unwrap_or_default();
+    }
+}
+
+// Run condition for systems that must not react to keys typed into the console
+pub fn console_closed(console: Res<DevConsole>) -> bool {
+    !console.open
+}
+
+#[derive(Component)]
+pub struct ConsoleOverlay;
+
+#[derive(Component)]
+pub struct ConsoleText;
+
+pub fn spawn_console(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                visibility: Visibility::Hidden,
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(40.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::FlexEnd,
+                    padding: UiRect::all(Val::Px(8.0)),
+                    overflow: Overflow::clip(),
+                    ..default()
+                },
+                background_color: BackgroundColor(Color::rgba(0.05, 0.05, 0.1, 0.9)),
+                z_index: ZIndex::Global(30),
+                ..default()
+            },
+            ConsoleOverlay,
+        ))
+        .with_children(|builder| {
+            builder.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font,
+                        font_size: 16.0,
+                        color: Color::rgb(0.8, 1.0, 0.8),
+                    },
+                ),
+                ConsoleText,
+            ));
+        });
+}
+
+pub fn console_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut console: ResMut<DevConsole>,
+    mut storage: ResMut<CoolFactStore>,
+    mut rules: ResMut<RuleEngine>,
+    mut stories: ResMut<StoryEngine>,
+    mut load_level: EventWriter<LoadLevel>,
+) {
+    if keys.just_pressed(KeyCode::Backquote) {
+        console.open = !console.open;
+    }
+    if !console.open {
+        characters.clear();
+        return;
+    }
+
+    for character in characters.read() {
+        let typed = character.char.chars().filter(|c| !c.is_control() && *c != '`');
+        console.input.extend(typed);
+    }
+    if keys.just_pressed(KeyCode::Backspace) {
+        console.input.pop();
+    }
+    if keys.just_pressed(KeyCode::ArrowUp) {
+        console.recall(true);
+    }
+    if keys.just_pressed(KeyCode::ArrowDown) {
+        console.recall(false);
+    }
+    if keys.just_pressed(KeyCode::Tab) {
+        let (line, candidates) = complete(&console.input, &storage, &rules, &stories);
+        if !candidates.is_empty() {
+            console.print(&candidates.join("  "));
+        }
+        console.input = line;
+    }
+    if keys.just_pressed(KeyCode::Enter) {
+        let line = std::mem::take(&mut console.input);
+        console.history_index = None;
+        if line.trim().is_empty() {
+            return;
+        }
+        console.print(&format!("> {}", line));
+        if console.history.last() != Some(&line) {
+            console.history.push(line.clone());
+        }
+        let console = &mut *console;
+        let result = parse_command(&line)
+            .and_then(|command| execute(command, &mut storage, &mut rules, &mut stories, &mut console.watches));
+        match result {
+            Ok(ConsoleReply::Text(text)) => console.print(&text),
+            Ok(ConsoleReply::LoadLevel(name)) => {
+                console.print(&format!("loading {}", name));
+                load_level.send(LoadLevel { name });
+            }
+            Err(error) => console.print(&format!("error: {}", error)),
+        }
+    }
+}
+
+// Print every change of a watched global fact
+pub fn print_watched_facts(mut console: ResMut<DevConsole>, mut updated: EventReader<FactUpdated>) {
+    for event in updated.read() {
+        if event.entity.is_none() && console.watches.contains(event.fact.key()) {
+            let line = format!("[watch] {}", describe_fact(&event.fact));
+            console.print(&line);
+        }
+    }
+}
+
+pub fn render_console(
+    console: Res<DevConsole>,
+    mut overlay: Query<&mut Visibility, With<ConsoleOverlay>>,
+    mut text: Query<&mut Text, With<ConsoleText>>,
+) {
+    if !console.is_changed() {
+        return;
+    }
+    for mut visibility in overlay.iter_mut() {
+        *visibility = if console.open { Visibility::Visible } else { Visibility::Hidden };
+    }
+    let start = console.output.len().saturating_sub(CONSOLE_OUTPUT_LINES);
+    let mut lines: Vec<&str> = console.output.range(start..).map(|line| line.as_str()).collect();
+    let input = format!("> {}_", console.input);
+    lines.push(&input);
+    for mut text in text.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Condition, Rule, Story, StoryBeat};
+
+    struct Console {
+        storage: CoolFactStore,
+        rules: RuleEngine,
+        stories: StoryEngine,
+        watches: BTreeSet<String>,
+    }
+
+    impl Console {
+        fn new() -> Self {
+            let mut storage = CoolFactStore::new();
+            storage.declare_fact(Fact::Int("player1.fuel".to_string(), 100));
+            storage.reset_namespace("player1");
+            storage.store_int("button_pressed".to_string(), 0);
+            storage.store_string("player1.name".to_string(), "Ada".to_string());
+            storage.store_float("player1.speed".to_string(), 1.5);
+
+            let mut rules = RuleEngine::new();
+            let condition = Condition::IntLessThan { fact_name: "player1.fuel".to_string(), expected_value: 10 };
+            rules.add_rule(Rule::new("low_fuel_rule".to_string(), vec![condition]));
+
+            let mut stories = StoryEngine::new();
+            let beats = vec![
+                StoryBeat::new("intro".to_string(), Vec::new()),
+                StoryBeat::new("ending".to_string(), Vec::new()),
+            ];
+            stories.add_story(Story::new("tutorial".to_string(), beats));
+
+            Console { storage, rules, stories, watches: BTreeSet::new() }
+        }
+
+        fn run(&mut self, line: &str) -> Result<ConsoleReply, ConsoleError> {
+            let command = parse_command(line)?;
+            execute(command, &mut self.storage, &mut self.rules, &mut self.stories, &mut self.watches)
+        }
+
+        fn text(&mut self, line: &str) -> String {
+            match self.run(line) {
+                Ok(ConsoleReply::Text(text)) => text,
+                other => panic!("{} gave {:?}", line, other),
+            }
+        }
+
+        fn fact(&self, key: &str) -> Option<&Fact> {
+            self.storage.facts.get(key)
+        }
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert_eq!(parse_command("   "), Err(ConsoleError::Empty));
+        assert_eq!(parse_command("fly"), Err(ConsoleError::UnknownCommand("fly".to_string())));
+        assert_eq!(parse_command("set fuel"), Err(ConsoleError::Usage("set <key> <value>")));
+        assert_eq!(parse_command("get"), Err(ConsoleError::Usage("get <key>")));
+        assert_eq!(parse_command("add fuel"), Err(ConsoleError::Usage("add <key> <amount>")));
+        assert_eq!(parse_command("add fuel lots"), Err(ConsoleError::NotANumber("lots".to_string())));
+        assert_eq!(parse_command("list a b"), Err(ConsoleError::Usage("list [namespace]")));
+        assert_eq!(parse_command("enable"), Err(ConsoleError::Usage("enable <rule>")));
+        assert_eq!(parse_command("disable a b"), Err(ConsoleError::Usage("disable <rule>")));
+        assert_eq!(parse_command("group level1 maybe"), Err(ConsoleError::Usage("group <group> on|off")));
+        assert_eq!(parse_command("beat tutorial"), Err(ConsoleError::Usage("beat <story> <beat>")));
+        assert_eq!(
+            parse_command("set greeting hello there"),
+            Ok(ConsoleCommand::Set { key: "greeting".to_string(), value: "hello there".to_string() })
+        );
+    }
+
+    #[test]
+    fn set_and_get() {
+        let mut console = Console::new();
+        assert_eq!(console.text("set player1.fuel 12"), "player1.fuel = 12");
+        assert_eq!(console.text("get player1.fuel"), "player1.fuel = 12 (Int)");
+        // New facts get the type their value looks like
+        console.text("set landed true");
+        assert_eq!(console.fact("landed"), Some(&Fact::Bool("landed".to_string(), true)));
+        assert_eq!(console.run("get nothing"), Err(ConsoleError::UnknownFact("nothing".to_string())));
+    }
+
+    #[test]
+    fn set_parses_the_value_as_the_type_of_the_fact() {
+        let mut console = Console::new();
+        assert_eq!(
+            console.run("set button_pressed true"),
+            Err(ConsoleError::WrongType { value: "true".to_string(), kind: FactKind::Int })
+        );
+        assert_eq!(
+            console.run("set player1.fuel 12.5"),
+            Err(ConsoleError::WrongType { value: "12.5".to_string(), kind: FactKind::Int })
+        );
+        assert_eq!(console.fact("player1.fuel"), Some(&Fact::Int("player1.fuel".to_string(), 100)));
+
+        // A string fact takes anything, a float fact takes ints
+        console.text("set player1.name 5");
+        assert_eq!(console.fact("player1.name"), Some(&Fact::String("player1.name".to_string(), "5".to_string())));
+        console.text("set player1.speed 2");
+        assert_eq!(console.fact("player1.speed"), Some(&Fact::Float("player1.speed".to_string(), FloatValue(2.0))));
+    }
+
+    #[test]
+    fn set_respects_the_declared_type_of_an_unset_fact() {
+        let mut console = Console::new();
+        console.storage.remove_fact("player1.fuel");
+        assert_eq!(
+            console.run("set player1.fuel 12.5"),
+            Err(ConsoleError::WrongType { value: "12.5".to_string(), kind: FactKind::Int })
+        );
+        // Resetting stores the declared default without a type clash
+        console.storage.reset_namespace("player1");
+        assert_eq!(console.fact("player1.fuel"), Some(&Fact::Int("player1.fuel".to_string(), 100)));
+    }
+
+    #[test]
+    fn add() {
+        let mut console = Console::new();
+        assert_eq!(console.text("add player1.fuel -30"), "player1.fuel = 70");
+        assert_eq!(console.text("add player1.speed 1"), "player1.speed = 2.5");
+        assert_eq!(console.text("add hits 2"), "hits = 2");
+        assert_eq!(
+            console.run("add player1.fuel 0.5"),
+            Err(ConsoleError::WrongType { value: "0.5".to_string(), kind: FactKind::Int })
+        );
+        assert_eq!(console.run("add player1.name 1"), Err(ConsoleError::NotNumeric("player1.name".to_string())));
+        assert_eq!(console.run("add player1.fuel 2147483647"), Err(ConsoleError::Overflow("player1.fuel".to_string())));
+        assert_eq!(console.fact("player1.fuel"), Some(&Fact::Int("player1.fuel".to_string(), 70)));
+    }
+
+    #[test]
+    fn list() {
+        let mut console = Console::new();
+        assert_eq!(console.text("list player1"), "player1.fuel = 100\nplayer1.name = Ada\nplayer1.speed = 1.5");
+        assert_eq!(console.text("list enemies"), "no facts");
+    }
+
+    #[test]
+    fn enable_and_disable() {
+        let mut console = Console::new();
+        assert_eq!(console.text("disable low_fuel_rule"), "disabled low_fuel_rule");
+        assert!(!console.rules.is_enabled("low_fuel_rule"));
+        assert_eq!(console.text("enable low_fuel_rule"), "enabled low_fuel_rule");
+        assert!(console.rules.is_enabled("low_fuel_rule"));
+        assert_eq!(console.run("enable no_rule"), Err(ConsoleError::UnknownRule("no_rule".to_string())));
+    }
+
+    #[test]
+    fn beat() {
+        let mut console = Console::new();
+        assert_eq!(console.text("beat tutorial ending"), "tutorial is at beat ending");
+        assert_eq!(console.stories.stories[0].active_beat_index, 1);
+        assert_eq!(
+            console.run("beat tutorial middle"),
+            Err(ConsoleError::UnknownBeat { story: "tutorial".to_string(), beat: "middle".to_string() })
+        );
+        assert_eq!(console.run("beat sequel intro"), Err(ConsoleError::UnknownStory("sequel".to_string())));
+    }
+
+    #[test]
+    fn completion() {
+        let console = Console::new();
+        let complete = |line: &str| complete(line, &console.storage, &console.rules, &console.stories);
+        assert_eq!(complete("ex"), ("explain ".to_string(), Vec::new()));
+        assert_eq!(complete("un"), ("unwatch ".to_string(), Vec::new()));
+        assert_eq!(complete("disable low"), ("disable low_fuel_rule ".to_string(), Vec::new()));
+        assert_eq!(complete("beat tutorial e"), ("beat tutorial ending ".to_string(), Vec::new()));
+        let (line, candidates) = complete("get player1.");
+        assert_eq!(line, "get player1.");
+        assert_eq!(candidates, vec!["player1.fuel", "player1.name", "player1.speed"]);
+        let (line, candidates) = complete("get player1.s");
+        assert_eq!((line.as_str(), candidates.len()), ("get player1.speed ", 0));
+    }
+}
@@ -0,0 +1,221 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::utils::hashbrown::{HashMap, HashSet};
+
+use crate::fact_history::FactTimeline;
+use crate::rule_timers::TimedEvaluation;
+use crate::{rule_enabled, CoolFactStore, Fact, FactRemoved, FactSource, FactUpdated, RuleEngine, RuleUpdated};
+
+/// Facts that belong to a single entity, such as one rokket, enemy or pod. It has the same
+/// typed API as the global `CoolFactStore`.
+#[derive(Component, Deref, DerefMut)]
+pub struct FactStore(pub CoolFactStore);
+
+impl FactStore {
+    // Constructor for FactStore
+    pub fn new() -> Self {
+        FactStore(CoolFactStore::new())
+    }
+}
+
+impl Default for FactStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An entity's facts with the global facts underneath, so per-entity rules can use both.
+pub struct LayeredFacts<'a> {
+    local: &'a dyn FactSource,
+    global: &'a dyn FactSource,
+}
+
+impl<'a> LayeredFacts<'a> {
+    // Constructor for LayeredFacts
+    pub fn new(local: &'a dyn FactSource, global: &'a dyn FactSource) -> Self {
+        LayeredFacts { local, global }
+    }
+}
+
+impl FactSource for LayeredFacts<'_> {
+    fn fact(&self, key: &str) -> Option<&Fact> {
+        self.local.fact(key).or_else(|| self.global.fact(key))
+    }
+
+    fn keys_under(&self, namespace: &str) -> Vec<&str> {
+        let mut keys = self.local.keys_under(namespace);
+        keys.extend(self.global.keys_under(namespace));
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    fn timeline(&self, key: &str) -> Option<&FactTimeline> {
+        match self.local.fact(key) {
+            Some(_) => self.local.timeline(key),
+            None => self.global.timeline(key),
+        }
+    }
+
+    fn now(&self) -> Duration {
+        self.global.now()
+    }
+}
+
+impl RuleEngine {
+    // Evaluate the per-entity rules for one entity based on its layered facts
+    pub fn evaluate_entity_rules(&mut self, entity: Entity, facts: &dyn FactSource) -> HashSet<String> {
+        let mut updated_rule_states = HashSet::new();
+        let states = self.entity_rule_states.entry(entity).or_default();
+        let firings = self.entity_firings.entry(entity).or_default();
+        let mut timed = TimedEvaluation::new(self.entity_timers.entry(entity).or_default(), facts.now());
+        let mut policy_wake_up: Option<Duration> = None;
+        for (name, rule) in self.rules.iter().filter(|(_, rule)| rule.per_entity) {
+            let previous_state = states.get(name).copied().unwrap_or(false);
+            let holds = rule_enabled(rule, &self.disabled_rules, &self.disabled_groups) && timed.rule(rule, facts);
+            let state = match self.policies.get(name) {
+                Some(policy) => {
+                    let firing = firings.entry(name.clone()).or_default();
+                    let (state, wake_up) = firing.apply(policy, holds, previous_state, facts.now());
+                    policy_wake_up = policy_wake_up.into_iter().chain(wake_up).min();
+                    state
+                }
+                None => holds,
+            };
+            if previous_state != state {
+                states.insert(name.clone(), state);
+                updated_rule_states.insert(name.clone());
+            }
+        }
+        match timed.wake_up().into_iter().chain(policy_wake_up).min() {
+            Some(at) => self.entity_wake_ups.insert(entity, at),
+            None => self.entity_wake_ups.remove(&entity),
+        };
+        updated_rule_states
+    }
+
+    pub fn entity_rule_state(&self, entity: Entity, rule: &str) -> bool {
+        self.entity_rule_states
+            .get(&entity)
+            .and_then(|states| states.get(rule))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    // Drop the rule states of an entity that no longer has facts
+    pub fn forget_entity(&mut self, entity: Entity) {
+        self.entity_rule_states.remove(&entity);
+        self.entity_timers.remove(&entity);
+        self.entity_firings.remove(&entity);
+        self.entity_wake_ups.remove(&entity);
+    }
+
+    // Entities whose per-entity rules have to be evaluated again because time passed
+    pub fn entities_due(&self, now: Duration) -> impl Iterator<Item = Entity> + '_ {
+        self.entity_wake_ups
+            .iter()
+            .filter(move |(_, at)| **at <= now)
+            .map(|(entity, _)| *entity)
+    }
+
+    pub fn has_per_entity_rules(&self) -> bool {
+        self.rules.values().any(|rule| rule.per_entity)
+    }
+}
+
+pub fn entity_fact_update_event_broadcaster(
+    mut event_writer: EventWriter<FactUpdated>,
+    mut removed_writer: EventWriter<FactRemoved>,
+    mut stores: Query<(Entity, &mut FactStore)>,
+) {
+    for (entity, mut store) in stores.iter_mut() {
+        if store.updated_facts.is_empty() && store.removed_facts.is_empty() {
+            continue;
+        }
+        for fact in store.updated_facts.drain() {
+            event_writer.send(FactUpdated {
+                fact,
+                entity: Some(entity),
+            });
+        }
+        for key in store.removed_facts.drain() {
+            removed_writer.send(FactRemoved {
+                key,
+                entity: Some(entity),
+            });
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn entity_rule_evaluator(
+    mut rules: ResMut<RuleEngine>,
+    mut fact_updated: EventReader<FactUpdated>,
+    mut fact_removed: EventReader<FactRemoved>,
+    mut rule_updated_writer: EventWriter<RuleUpdated>,
+    storage: Res<CoolFactStore>,
+    stores: Query<(Entity, &FactStore)>,
+    added_stores: Query<Entity, Added<FactStore>>,
+    mut seen_revision: Local<(u64, u64)>,
+) {
+    if !rules.has_per_entity_rules() {
+        fact_updated.clear();
+        fact_removed.clear();
+        return;
+    }
+    // A global change can affect every entity through the fallback, an entity change
+    // only that entity
+    // Enabling, disabling and policies change states the same way rules changing does
+    let revision = (rules.revision(), rules.settings_revision());
+    let rules_changed = *seen_revision != revision;
+    *seen_revision = revision;
+    let mut global_changed = rules_changed;
+    let mut changed_entities: HashSet<Entity> = added_stores.iter().collect();
+    changed_entities.extend(rules.entities_due(storage.now()));
+    // The changed keys, kept to say which facts triggered a rule
+    let mut global_keys: HashSet<String> = HashSet::new();
+    let mut entity_keys: HashMap<Entity, HashSet<String>> = HashMap::new();
+    let changes = fact_updated.read().map(|event| (event.entity, event.fact.key().to_string()))
+        .chain(fact_removed.read().map(|event| (event.entity, event.key.clone())));
+    for (entity, key) in changes {
+        match entity {
+            Some(entity) => {
+                changed_entities.insert(entity);
+                entity_keys.entry(entity).or_default().insert(key);
+            }
+            None => {
+                global_changed = true;
+                global_keys.insert(key);
+            }
+        }
+    }
+
+    for (entity, store) in stores.iter() {
+        if !global_changed && !changed_entities.contains(&entity) {
+            continue;
+        }
+        let facts = LayeredFacts::new(&store.0, &*storage);
+        let mut changed_keys = global_keys.clone();
+        changed_keys.extend(entity_keys.remove(&entity).unwrap_or_default());
+        for rule_name in rules.evaluate_entity_rules(entity, &facts) {
+            rule_updated_writer.send(RuleUpdated {
+                new_state: rules.entity_rule_state(entity, &rule_name),
+                triggered_by: rules.facts_read_by(&rule_name, &changed_keys),
+                rule: rule_name,
+                entity: Some(entity),
+                frame: storage.frame,
+                bindings: Vec::new(),
+            });
+        }
+    }
+}
+
+pub fn forget_removed_fact_stores(
+    mut rules: ResMut<RuleEngine>,
+    mut removed: RemovedComponents<FactStore>,
+) {
+    for entity in removed.read() {
+        rules.forget_entity(entity);
+    }
+}
@@ -0,0 +1,116 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::fact_batch::FactBatch;
+use crate::{CoolFactStore, Fact};
+
+/// A fact change applied for every event of a mapped type. Simple mappings can be written
+/// as data, e.g. in RON, instead of as a closure.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum EventFactAction {
+    Increment { key: String, by: i32 },
+    Store { fact: Fact },
+    AddToList { key: String, value: String },
+    Remove { key: String },
+}
+
+impl EventFactAction {
+    // Stage the action in a batch, so a type mismatch is caught by `CoolFactStore::commit`
+    pub fn apply(&self, batch: &mut FactBatch) {
+        match self {
+            EventFactAction::Increment { key, by } => batch.add_to_int(key.clone(), *by),
+            EventFactAction::Store { fact } => batch.store_fact(fact.clone(), None),
+            EventFactAction::AddToList { key, value } => batch.add_to_list(key.clone(), value.clone()),
+            EventFactAction::Remove { key } => batch.remove_fact(key.clone()),
+        };
+    }
+}
+
+pub trait EventFactsAppExt {
+    // Call `map` with the global facts for every event of type `E`
+    fn map_event_to_fact<E: Event>(
+        &mut self,
+        map: impl Fn(&E, &mut CoolFactStore) + Send + Sync + 'static,
+    ) -> &mut Self;
+
+    // Apply the actions to the global facts for every event of type `E`, all at once. If one
+    // doesn't fit the type of its fact none of them are applied and a warning is logged.
+    fn map_event_to_actions<E: Event>(&mut self, actions: Vec<EventFactAction>) -> &mut Self;
+
+    // Count the events of type `E` in an int fact
+    fn count_event_as_fact<E: Event>(&mut self, key: &str) -> &mut Self;
+}
+
+impl EventFactsAppExt for App {
+    fn map_event_to_fact<E: Event>(
+        &mut self,
+        map: impl Fn(&E, &mut CoolFactStore) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.add_event::<E>().add_systems(
+            Update,
+            move |mut events: EventReader<E>, mut storage: ResMut<CoolFactStore>| {
+                for event in events.read() {
+                    map(event, &mut storage);
+                }
+            },
+        )
+    }
+
+    fn map_event_to_actions<E: Event>(&mut self, actions: Vec<EventFactAction>) -> &mut Self {
+        self.map_event_to_fact::<E>(move |_, storage| {
+            let mut batch = FactBatch::new();
+            for action in &actions {
+                action.apply(&mut batch);
+            }
+            if let Err(error) = storage.commit(batch) {
+                warn!("Event {} wasn't applied to the facts: {}", std::any::type_name::<E>(), error);
+            }
+        })
+    }
+
+    fn count_event_as_fact<E: Event>(&mut self, key: &str) -> &mut Self {
+        self.map_event_to_actions::<E>(vec![EventFactAction::Increment {
+            key: key.to_string(),
+            by: 1,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(actions: &[EventFactAction], store: &mut CoolFactStore) -> Result<(), crate::fact_batch::FactError> {
+        let mut batch = FactBatch::new();
+        for action in actions {
+            action.apply(&mut batch);
+        }
+        store.commit(batch)
+    }
+
+    #[test]
+    fn actions_are_applied_together() {
+        let mut store = CoolFactStore::new();
+        let actions = [
+            EventFactAction::Increment { key: "hits".to_string(), by: 2 },
+            EventFactAction::AddToList { key: "seen".to_string(), value: "pod".to_string() },
+        ];
+        commit(&actions, &mut store).unwrap();
+        commit(&actions, &mut store).unwrap();
+        assert_eq!(store.get_int("hits"), Some(&4));
+        assert!(matches!(store.facts.get("seen"), Some(Fact::StringList(_, list)) if list.contains("pod")));
+    }
+
+    #[test]
+    fn a_mismatched_action_applies_nothing() {
+        let mut store = CoolFactStore::new();
+        store.store_bool("landed".to_string(), true);
+        let actions = [
+            EventFactAction::Store { fact: Fact::Int("hits".to_string(), 1) },
+            EventFactAction::Increment { key: "landed".to_string(), by: 1 },
+        ];
+        assert!(commit(&actions, &mut store).is_err());
+        assert!(!store.facts.contains_key("hits"));
+        assert_eq!(store.facts.get("landed"), Some(&Fact::Bool("landed".to_string(), true)));
+    }
+}
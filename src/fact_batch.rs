@@ -0,0 +1,322 @@
+use std::fmt;
+use std::time::Duration;
+
+use bevy::utils::hashbrown::HashMap;
+
+use crate::{CoolFactStore, Fact, FactKind, StringHashSet};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FactError {
+    TypeMismatch { key: String, expected: FactKind, found: FactKind },
+    Overflow { key: String },
+}
+
+impl fmt::Display for FactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FactError::TypeMismatch { key, expected, found } => {
+                write!(f, "fact {} is {:?}, not {:?}", key, expected, found)
+            }
+            FactError::Overflow { key } => write!(f, "fact {} would overflow", key),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FactOp {
+    Store(Fact, Option<Duration>),
+    AddToInt(String, i32),
+    AddToList(String, String),
+    RemoveFromList(String, String),
+    Remove(String),
+}
+
+impl FactOp {
+    pub fn key(&self) -> &str {
+        match self {
+            FactOp::Store(fact, _) => fact.key(),
+            FactOp::AddToInt(key, _)
+            | FactOp::AddToList(key, _)
+            | FactOp::RemoveFromList(key, _)
+            | FactOp::Remove(key) => key,
+        }
+    }
+
+    // The kind of fact this operation works on, if it cares
+    fn kind(&self) -> Option<FactKind> {
+        match self {
+            FactOp::Store(fact, _) => Some(fact.kind()),
+            FactOp::AddToInt(..) => Some(FactKind::Int),
+            FactOp::AddToList(..) | FactOp::RemoveFromList(..) => Some(FactKind::StringList),
+            FactOp::Remove(_) => None,
+        }
+    }
+
+    // Apply the operation to the staged value of its fact
+    fn apply(self, current: Option<Fact>) -> Result<Option<Fact>, FactError> {
+        Ok(match self {
+            FactOp::Store(fact, _) => Some(fact),
+            FactOp::AddToInt(key, value) => {
+                let current = match current {
+                    Some(Fact::Int(_, current)) => current,
+                    _ => 0,
+                };
+                let Some(sum) = current.checked_add(value) else {
+                    return Err(FactError::Overflow { key });
+                };
+                Some(Fact::Int(key, sum))
+            }
+            FactOp::AddToList(key, value) => {
+                let mut list = match current {
+                    Some(Fact::StringList(_, list)) => list,
+                    _ => StringHashSet::new(),
+                };
+                list.insert(value);
+                Some(Fact::StringList(key, list))
+            }
+            FactOp::RemoveFromList(_, value) => match current {
+                Some(Fact::StringList(key, mut list)) => {
+                    list.remove(&value);
+                    Some(Fact::StringList(key, list))
+                }
+                other => other,
+            },
+            FactOp::Remove(_) => None,
+        })
+    }
+}
+
+/// A list of fact changes that `CoolFactStore::commit` applies all at once, or not at all.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FactBatch {
+    ops: Vec<FactOp>,
+}
+
+impl FactBatch {
+    // Constructor for FactBatch
+    pub fn new() -> Self {
+        FactBatch { ops: Vec::new() }
+    }
+
+    pub fn store_int(&mut self, key: String, value: i32) -> &mut Self {
+        self.store_fact(Fact::Int(key, value), None)
+    }
+
+    pub fn store_string(&mut self, key: String, value: String) -> &mut Self {
+        self.store_fact(Fact::String(key, value), None)
+    }
+
+    pub fn store_bool(&mut self, key: String, value: bool) -> &mut Self {
+        self.store_fact(Fact::Bool(key, value), None)
+    }
+
+    pub fn store_fact(&mut self, fact: Fact, ttl: Option<Duration>) -> &mut Self {
+        self.ops.push(FactOp::Store(fact, ttl));
+        self
+    }
+
+    pub fn add_to_int(&mut self, key: String, value: i32) -> &mut Self {
+        self.ops.push(FactOp::AddToInt(key, value));
+        self
+    }
+
+    pub fn add_to_list(&mut self, key: String, value: String) -> &mut Self {
+        self.ops.push(FactOp::AddToList(key, value));
+        self
+    }
+
+    pub fn remove_from_list(&mut self, key: String, value: String) -> &mut Self {
+        self.ops.push(FactOp::RemoveFromList(key, value));
+        self
+    }
+
+    pub fn remove_fact(&mut self, key: String) -> &mut Self {
+        self.ops.push(FactOp::Remove(key));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+impl CoolFactStore {
+    // Apply every change in the batch, or none of them if any change has the wrong type for
+    // its fact, either the type it currently has or the type it was declared with, or would
+    // overflow an int. A fact removed earlier in the batch can be stored as any type it
+    // wasn't declared with. Each fact
+    // is updated once with its final value, so only the committed state is broadcast.
+    pub fn commit(&mut self, batch: FactBatch) -> Result<(), FactError> {
+        let mut order: Vec<String> = Vec::new();
+        let mut staged: HashMap<String, Option<Fact>> = HashMap::new();
+        let mut ttls: Vec<(String, Duration)> = Vec::new();
+
+        for op in batch.ops {
+            let key = op.key().to_string();
+            let current = match staged.get(&key) {
+                Some(staged_value) => staged_value.clone(),
+                None => self.facts.get(&key).cloned(),
+            };
+            let existing_kind = current
+                .as_ref()
+                .or_else(|| self.defaults.get(&key))
+                .map(Fact::kind);
+            if let (Some(expected), Some(found)) = (existing_kind, op.kind()) {
+                if expected != found {
+                    return Err(FactError::TypeMismatch { key, expected, found });
+                }
+            }
+            if let FactOp::Store(_, Some(ttl)) = &op {
+                ttls.push((key.clone(), *ttl));
+            }
+            if !staged.contains_key(&key) {
+                order.push(key.clone());
+            }
+            staged.insert(key, op.apply(current)?);
+        }
+
+        for key in order {
+            match staged.remove(&key).flatten() {
+                Some(fact) => {
+                    // Removed and stored again as another type within the batch
+                    if self.facts.get(&key).is_some_and(|current| current.kind() != fact.kind()) {
+                        self.remove_fact(&key);
+                    }
+                    self.store_fact(fact, None)
+                }
+                None => {
+                    self.remove_fact(&key);
+                }
+            }
+        }
+        for (key, ttl) in ttls {
+            if self.facts.contains_key(&key) {
+                self.expire_after(&key, ttl);
+            }
+        }
+        Ok(())
+    }
+
+    // Build a batch in the closure and commit it
+    pub fn transaction(&mut self, build: impl FnOnce(&mut FactBatch)) -> Result<(), FactError> {
+        let mut batch = FactBatch::new();
+        build(&mut batch);
+        self.commit(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> CoolFactStore {
+        let mut store = CoolFactStore::new();
+        store.store_int("fuel".to_string(), 10);
+        store.store_bool("landed".to_string(), false);
+        store.declare_fact(Fact::Int("lives".to_string(), 3));
+        store.updated_facts.clear();
+        store
+    }
+
+    fn snapshot(store: &CoolFactStore) -> Vec<Fact> {
+        let mut facts: Vec<Fact> = store.facts.values().cloned().collect();
+        facts.sort_by(|a, b| a.key().cmp(b.key()));
+        facts
+    }
+
+    #[test]
+    fn a_mismatch_rolls_back_the_whole_batch() {
+        let mut store = store();
+        let before = snapshot(&store);
+        let result = store.transaction(|batch| {
+            batch.store_int("fuel".to_string(), 5).store_string("name".to_string(), "Ada".to_string());
+            batch.add_to_int("landed".to_string(), 1);
+        });
+        assert_eq!(
+            result,
+            Err(FactError::TypeMismatch { key: "landed".to_string(), expected: FactKind::Bool, found: FactKind::Int })
+        );
+        assert_eq!(snapshot(&store), before);
+        assert!(store.updated_facts.is_empty());
+    }
+
+    #[test]
+    fn the_declared_type_is_checked_for_unset_facts() {
+        let mut store = store();
+        let result = store.transaction(|batch| {
+            batch.store_bool("lives".to_string(), true);
+        });
+        assert_eq!(
+            result,
+            Err(FactError::TypeMismatch { key: "lives".to_string(), expected: FactKind::Int, found: FactKind::Bool })
+        );
+        assert!(!store.facts.contains_key("lives"));
+    }
+
+    #[test]
+    fn an_overflow_rolls_back_the_whole_batch() {
+        let mut store = store();
+        let result = store.transaction(|batch| {
+            batch.store_bool("landed".to_string(), true).add_to_int("fuel".to_string(), i32::MAX);
+        });
+        assert_eq!(result, Err(FactError::Overflow { key: "fuel".to_string() }));
+        assert_eq!(store.facts.get("landed"), Some(&Fact::Bool("landed".to_string(), false)));
+    }
+
+    #[test]
+    fn only_the_final_value_is_stored() {
+        let mut store = store();
+        store
+            .transaction(|batch| {
+                batch.add_to_int("fuel".to_string(), 5).add_to_int("fuel".to_string(), -3);
+                batch.add_to_list("pods".to_string(), "one".to_string());
+                batch.add_to_list("pods".to_string(), "two".to_string());
+                batch.remove_from_list("pods".to_string(), "one".to_string());
+            })
+            .unwrap();
+        let pods: StringHashSet = ["two"].into_iter().collect();
+        assert_eq!(store.facts.get("fuel"), Some(&Fact::Int("fuel".to_string(), 12)));
+        assert_eq!(store.facts.get("pods"), Some(&Fact::StringList("pods".to_string(), pods)));
+        assert_eq!(store.updated_facts.len(), 2);
+    }
+
+    #[test]
+    fn remove_then_store_as_another_type() {
+        let mut store = store();
+        store
+            .transaction(|batch| {
+                batch.remove_fact("landed".to_string()).store_int("landed".to_string(), 1);
+            })
+            .unwrap();
+        assert_eq!(store.facts.get("landed"), Some(&Fact::Int("landed".to_string(), 1)));
+
+        store
+            .transaction(|batch| {
+                batch.store_int("fuel".to_string(), 1).remove_fact("fuel".to_string());
+            })
+            .unwrap();
+        assert!(!store.facts.contains_key("fuel"));
+    }
+
+    #[test]
+    fn time_to_live() {
+        let mut store = store();
+        store
+            .transaction(|batch| {
+                batch.store_fact(Fact::Bool("boosting".to_string(), true), Some(Duration::from_secs(2)));
+                batch.store_fact(Fact::Int("gone".to_string(), 1), Some(Duration::from_secs(2)));
+                batch.remove_fact("gone".to_string());
+            })
+            .unwrap();
+        assert_eq!(store.time_to_live("boosting"), Some(Duration::from_secs(2)));
+        assert_eq!(store.time_to_live("gone"), None);
+
+        // A rolled back batch doesn't start a countdown either
+        let result = store.transaction(|batch| {
+            batch.store_fact(Fact::Int("fuel".to_string(), 1), Some(Duration::from_secs(2)));
+            batch.store_int("landed".to_string(), 1);
+        });
+        assert!(result.is_err());
+        assert_eq!(store.time_to_live("fuel"), None);
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt;
+use std::ops;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Fact, FactSource, FloatValue};
+
+/// Arithmetic over numeric facts and literals, e.g. `kills - deaths`. Int and float facts
+/// can be mixed freely.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum FactExpr {
+    Fact(String),
+    Int(i32),
+    Float(FloatValue),
+    Add(Box<FactExpr>, Box<FactExpr>),
+    Sub(Box<FactExpr>, Box<FactExpr>),
+    Mul(Box<FactExpr>, Box<FactExpr>),
+    Div(Box<FactExpr>, Box<FactExpr>),
+}
+
+impl FactExpr {
+    pub fn fact(key: &str) -> Self {
+        FactExpr::Fact(key.to_string())
+    }
+
+    // The value of the expression, or `None` if a fact is missing or not a number, or
+    // something is divided by zero
+    pub fn evaluate(&self, facts: &dyn FactSource) -> Option<f64> {
+        match self {
+            FactExpr::Fact(key) => match facts.fact(key)? {
+                Fact::Int(_, value) => Some(*value as f64),
+                Fact::Float(_, value) => Some(value.0 as f64),
+                _ => None,
+            },
+            FactExpr::Int(value) => Some(*value as f64),
+            FactExpr::Float(value) => Some(value.0 as f64),
+            FactExpr::Add(left, right) => Some(left.evaluate(facts)? + right.evaluate(facts)?),
+            FactExpr::Sub(left, right) => Some(left.evaluate(facts)? - right.evaluate(facts)?),
+            FactExpr::Mul(left, right) => Some(left.evaluate(facts)? * right.evaluate(facts)?),
+            FactExpr::Div(left, right) => {
+                let divisor = right.evaluate(facts)?;
+                if divisor == 0.0 {
+                    return None;
+                }
+                Some(left.evaluate(facts)? / divisor)
+            }
+        }
+    }
+
+    // Every fact the expression reads, in order of appearance
+    pub fn fact_names(&self) -> Vec<&str> {
+        match self {
+            FactExpr::Fact(key) => vec![key.as_str()],
+            FactExpr::Int(_) | FactExpr::Float(_) => Vec::new(),
+            FactExpr::Add(left, right)
+            | FactExpr::Sub(left, right)
+            | FactExpr::Mul(left, right)
+            | FactExpr::Div(left, right) => {
+                let mut names = left.fact_names();
+                names.extend(right.fact_names());
+                names
+            }
+        }
+    }
+}
+
+impl fmt::Display for FactExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Nested operations are wrapped in parentheses so the grouping is never ambiguous
+        let operand = |expr: &FactExpr| match expr {
+            FactExpr::Fact(_) | FactExpr::Int(_) | FactExpr::Float(_) => expr.to_string(),
+            _ => format!("({})", expr),
+        };
+        match self {
+            FactExpr::Fact(key) => write!(f, "{}", key),
+            FactExpr::Int(value) => write!(f, "{}", value),
+            FactExpr::Float(value) => write!(f, "{}", value.0),
+            FactExpr::Add(left, right) => write!(f, "{} + {}", operand(left), operand(right)),
+            FactExpr::Sub(left, right) => write!(f, "{} - {}", operand(left), operand(right)),
+            FactExpr::Mul(left, right) => write!(f, "{} * {}", operand(left), operand(right)),
+            FactExpr::Div(left, right) => write!(f, "{} / {}", operand(left), operand(right)),
+        }
+    }
+}
+
+impl From<i32> for FactExpr {
+    fn from(value: i32) -> Self {
+        FactExpr::Int(value)
+    }
+}
+
+impl From<f32> for FactExpr {
+    fn from(value: f32) -> Self {
+        FactExpr::Float(FloatValue(value))
+    }
+}
+
+impl<T: Into<FactExpr>> ops::Add<T> for FactExpr {
+    type Output = FactExpr;
+
+    fn add(self, right: T) -> FactExpr {
+        FactExpr::Add(Box::new(self), Box::new(right.into()))
+    }
+}
+
+impl<T: Into<FactExpr>> ops::Sub<T> for FactExpr {
+    type Output = FactExpr;
+
+    fn sub(self, right: T) -> FactExpr {
+        FactExpr::Sub(Box::new(self), Box::new(right.into()))
+    }
+}
+
+impl<T: Into<FactExpr>> ops::Mul<T> for FactExpr {
+    type Output = FactExpr;
+
+    fn mul(self, right: T) -> FactExpr {
+        FactExpr::Mul(Box::new(self), Box::new(right.into()))
+    }
+}
+
+impl<T: Into<FactExpr>> ops::Div<T> for FactExpr {
+    type Output = FactExpr;
+
+    fn div(self, right: T) -> FactExpr {
+        FactExpr::Div(Box::new(self), Box::new(right.into()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    pub fn compare(self, left: f64, right: f64) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bevy::utils::hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::Fact;
+
+/// A single recorded change of a fact, stamped with the frame and elapsed time it happened at.
+/// `value` is `None` when the fact was removed.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FactChange {
+    pub frame: u32,
+    pub elapsed: Duration,
+    pub value: Option<Fact>,
+}
+
+/// Bounded, oldest-first list of changes for one fact key.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FactTimeline {
+    capacity: usize,
+    changes: VecDeque<FactChange>,
+    // The value from before the oldest change: what the fact held when tracking started, or
+    // the last change dropped to make room. Looked up by `value_at`, but never counted as a
+    // change.
+    #[serde(default)]
+    baseline: Option<FactChange>,
+}
+
+impl FactTimeline {
+    // Constructor for FactTimeline
+    pub fn new(capacity: usize) -> Self {
+        FactTimeline {
+            capacity,
+            changes: VecDeque::with_capacity(capacity),
+            baseline: None,
+        }
+    }
+
+    // Record a change, dropping the oldest one when the timeline is full
+    pub fn record(&mut self, change: FactChange) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.changes.len() == self.capacity {
+            self.baseline = self.changes.pop_front();
+        }
+        self.changes.push_back(change);
+    }
+
+    // Set what the fact held before any recorded change
+    pub fn seed(&mut self, baseline: FactChange) {
+        self.baseline = Some(baseline);
+    }
+
+    // How many changes the timeline keeps
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn changes(&self) -> impl DoubleEndedIterator<Item = &FactChange> {
+        self.changes.iter()
+    }
+
+    pub fn last_change(&self) -> Option<&FactChange> {
+        self.changes.back()
+    }
+
+    // The value the fact had at the given elapsed time, if it was recorded by then
+    pub fn value_at(&self, at: Duration) -> Option<&Fact> {
+        self.changes
+            .iter()
+            .rev()
+            .chain(&self.baseline)
+            .find(|change| change.elapsed <= at)
+            .and_then(|change| change.value.as_ref())
+    }
+
+    // Number of changes that happened within `window` before `now`
+    pub fn changes_within(&self, window: Duration, now: Duration) -> usize {
+        self.changes
+            .iter()
+            .rev()
+            .take_while(|change| now.saturating_sub(change.elapsed) <= window)
+            .count()
+    }
+
+    pub fn time_since_last_change(&self, now: Duration) -> Option<Duration> {
+        self.last_change()
+            .map(|change| now.saturating_sub(change.elapsed))
+    }
+
+    // The last time the fact held `value`: `now` if it still does, otherwise the moment
+    // it changed away from it.
+    pub fn last_held(&self, value: &Fact, now: Duration) -> Option<Duration> {
+        let mut next_change_at = now;
+        for change in self.changes.iter().rev().chain(&self.baseline) {
+            if change.value.as_ref() == Some(value) {
+                return Some(next_change_at);
+            }
+            next_change_at = change.elapsed;
+        }
+        None
+    }
+}
+
+/// Opt-in change history for selected fact keys. Keys that are not tracked cost nothing.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct FactHistory {
+    timelines: HashMap<String, FactTimeline>,
+}
+
+impl FactHistory {
+    // Constructor for FactHistory
+    pub fn new() -> Self {
+        FactHistory {
+            timelines: HashMap::new(),
+        }
+    }
+
+    // Start keeping the last `capacity` changes for the given key
+    pub fn track(&mut self, key: String, capacity: usize) {
+        self.timelines
+            .entry(key)
+            .or_insert_with(|| FactTimeline::new(capacity));
+    }
+
+    pub fn untrack(&mut self, key: &str) {
+        self.timelines.remove(key);
+    }
+
+    pub fn is_tracked(&self, key: &str) -> bool {
+        self.timelines.contains_key(key)
+    }
+
+    // Set the value a tracked fact had when tracking started, which isn't a change
+    pub fn seed(&mut self, fact: &Fact, frame: u32, elapsed: Duration) {
+        if let Some(timeline) = self.timelines.get_mut(fact.key()) {
+            timeline.seed(FactChange {
+                frame,
+                elapsed,
+                value: Some(fact.clone()),
+            });
+        }
+    }
+
+    // Record a change for the fact if its key is tracked
+    pub fn record(&mut self, fact: &Fact, frame: u32, elapsed: Duration) {
+        if let Some(timeline) = self.timelines.get_mut(fact.key()) {
+            timeline.record(FactChange {
+                frame,
+                elapsed,
+                value: Some(fact.clone()),
+            });
+        }
+    }
+
+    // Record that the fact was removed if its key is tracked
+    pub fn record_removal(&mut self, key: &str, frame: u32, elapsed: Duration) {
+        if let Some(timeline) = self.timelines.get_mut(key) {
+            timeline.record(FactChange {
+                frame,
+                elapsed,
+                value: None,
+            });
+        }
+    }
+
+    pub fn timeline(&self, key: &str) -> Option<&FactTimeline> {
+        self.timelines.get(key)
+    }
+
+    pub fn value_at(&self, key: &str, at: Duration) -> Option<&Fact> {
+        self.timeline(key).and_then(|timeline| timeline.value_at(at))
+    }
+
+    pub fn changes_within(&self, key: &str, window: Duration, now: Duration) -> usize {
+        self.timeline(key)
+            .map_or(0, |timeline| timeline.changes_within(window, now))
+    }
+
+    pub fn time_since_last_change(&self, key: &str, now: Duration) -> Option<Duration> {
+        self.timeline(key)
+            .and_then(|timeline| timeline.time_since_last_change(now))
+    }
+
+    pub fn last_held(&self, value: &Fact, now: Duration) -> Option<Duration> {
+        self.timeline(value.key())
+            .and_then(|timeline| timeline.last_held(value, now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn fuel(value: i32) -> Fact {
+        Fact::Int("fuel".to_string(), value)
+    }
+
+    fn tracked(capacity: usize) -> FactHistory {
+        let mut history = FactHistory::new();
+        history.track("fuel".to_string(), capacity);
+        history.seed(&fuel(100), 0, secs(1));
+        history
+    }
+
+    #[test]
+    fn the_value_at_tracking_start_is_not_a_change() {
+        let history = tracked(4);
+        assert_eq!(history.value_at("fuel", secs(2)), Some(&fuel(100)));
+        assert_eq!(history.value_at("fuel", secs(0)), None);
+        assert_eq!(history.changes_within("fuel", secs(10), secs(2)), 0);
+        assert_eq!(history.time_since_last_change("fuel", secs(2)), None);
+        assert_eq!(history.last_held(&fuel(100), secs(2)), Some(secs(2)));
+    }
+
+    #[test]
+    fn changes_are_counted_from_the_baseline_on() {
+        let mut history = tracked(4);
+        history.record(&fuel(90), 1, secs(3));
+        assert_eq!(history.changes_within("fuel", secs(10), secs(4)), 1);
+        assert_eq!(history.time_since_last_change("fuel", secs(4)), Some(secs(1)));
+        assert_eq!(history.value_at("fuel", secs(2)), Some(&fuel(100)));
+        assert_eq!(history.last_held(&fuel(100), secs(4)), Some(secs(3)));
+    }
+
+    #[test]
+    fn a_dropped_change_becomes_the_baseline() {
+        let mut history = tracked(2);
+        history.record(&fuel(90), 1, secs(2));
+        history.record(&fuel(80), 2, secs(3));
+        history.record(&fuel(70), 3, secs(4));
+        assert_eq!(history.changes_within("fuel", secs(10), secs(4)), 2);
+        assert_eq!(history.value_at("fuel", secs(2)), Some(&fuel(90)));
+        // Older than anything kept
+        assert_eq!(history.value_at("fuel", secs(1)), None);
+    }
+}
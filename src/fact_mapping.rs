@@ -0,0 +1,125 @@
+use bevy::prelude::*;
+
+use crate::entity_facts::FactStore;
+use crate::CoolFactStore;
+
+pub use facts_derive::Facts;
+
+/// Maps the fields of a component or resource to facts. Usually derived with
+/// `#[derive(Facts)]`, see `facts_derive` for the attributes it supports.
+pub trait Facts {
+    // The prefix the facts are stored under unless another one is given
+    fn fact_prefix() -> &'static str;
+
+    // Store every mapped field as a fact under the prefix. Facts that already hold the
+    // field's value are left alone, so only changed fields are broadcast.
+    fn write_facts_under(&self, store: &mut CoolFactStore, prefix: &str);
+
+    // Load every mapped field that has a fact of the right type under the prefix
+    fn read_facts_under(&mut self, store: &CoolFactStore, prefix: &str);
+
+    fn write_facts(&self, store: &mut CoolFactStore) {
+        self.write_facts_under(store, Self::fact_prefix());
+    }
+
+    fn read_facts(&mut self, store: &CoolFactStore) {
+        self.read_facts_under(store, Self::fact_prefix());
+    }
+}
+
+// Join a prefix and a fact name into a namespaced key
+pub fn fact_key(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+// Write changed components into the entity's own `FactStore` if it has one, otherwise
+// into the global store
+pub fn write_component_facts<T: Facts + Component>(
+    mut query: Query<(&T, Option<&mut FactStore>), Changed<T>>,
+    mut storage: ResMut<CoolFactStore>,
+) {
+    for (component, entity_store) in query.iter_mut() {
+        match entity_store {
+            Some(mut entity_store) => component.write_facts(&mut entity_store),
+            None => component.write_facts(&mut storage),
+        }
+    }
+}
+
+pub fn write_resource_facts<T: Facts + Resource>(
+    resource: Res<T>,
+    mut storage: ResMut<CoolFactStore>,
+) {
+    if resource.is_changed() {
+        resource.write_facts(&mut storage);
+    }
+}
+
+pub trait FactMappingAppExt {
+    // Keep the facts of a `Facts` component up to date whenever it changes
+    fn sync_component_facts<T: Facts + Component>(&mut self) -> &mut Self;
+
+    // Keep the facts of a `Facts` resource up to date whenever it changes
+    fn sync_resource_facts<T: Facts + Resource>(&mut self) -> &mut Self;
+}
+
+impl FactMappingAppExt for App {
+    fn sync_component_facts<T: Facts + Component>(&mut self) -> &mut Self {
+        self.add_systems(Update, write_component_facts::<T>)
+    }
+
+    fn sync_resource_facts<T: Facts + Resource>(&mut self) -> &mut Self {
+        self.add_systems(Update, write_resource_facts::<T>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Fact;
+
+    #[derive(Facts, Default)]
+    #[facts(prefix = "pod", crate = "crate")]
+    struct Pod {
+        cargo: u8,
+        #[fact(rename = "height")]
+        altitude: f32,
+        #[fact(kind = "string")]
+        serial: u64,
+        delivered: bool,
+        #[fact(skip)]
+        _velocity: i64,
+    }
+
+    #[test]
+    fn writes_and_reads_mapped_fields() {
+        let mut store = CoolFactStore::new();
+        let pod = Pod { cargo: 200, altitude: 1.5, serial: u64::MAX, delivered: true, _velocity: 3 };
+        pod.write_facts(&mut store);
+        assert_eq!(store.facts.get("pod.cargo"), Some(&Fact::Int("pod.cargo".to_string(), 200)));
+        assert_eq!(store.get_float("pod.height"), Some(&1.5));
+        assert_eq!(store.get_string("pod.serial"), Some(&u64::MAX.to_string()));
+        assert_eq!(store.get_bool("pod.delivered"), Some(&true));
+        assert!(!store.facts.contains_key("pod._velocity"));
+
+        let mut read = Pod::default();
+        read.read_facts(&store);
+        assert_eq!((read.cargo, read.altitude, read.serial, read.delivered), (200, 1.5, u64::MAX, true));
+    }
+
+    #[test]
+    fn ints_that_dont_fit_the_field_are_not_read() {
+        let mut store = CoolFactStore::new();
+        store.store_int("pod.cargo".to_string(), 300);
+        let mut pod = Pod { cargo: 7, ..Pod::default() };
+        pod.read_facts(&store);
+        assert_eq!(pod.cargo, 7);
+        store.store_int("pod.cargo".to_string(), -1);
+        pod.read_facts(&store);
+        assert_eq!(pod.cargo, 7);
+    }
+}
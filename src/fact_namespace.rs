@@ -0,0 +1,135 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use crate::fact_history::FactTimeline;
+use crate::{CoolFactStore, Fact, FactSource};
+
+// Whether the key is the namespace itself or lives somewhere below it
+pub fn is_under(key: &str, namespace: &str) -> bool {
+    match key.strip_prefix(namespace) {
+        Some(rest) => rest.is_empty() || rest.starts_with('.'),
+        None => false,
+    }
+}
+
+// The distinct direct children of a namespace, e.g. `players.one` and `players.two` for
+// `players`, in sorted order
+pub fn child_namespaces(facts: &dyn FactSource, namespace: &str) -> Vec<String> {
+    let mut children = BTreeSet::new();
+    for key in facts.keys_under(namespace) {
+        if let Some(rest) = key.strip_prefix(namespace).and_then(|rest| rest.strip_prefix('.')) {
+            let child = rest.split('.').next().unwrap_or(rest);
+            children.insert(format!("{}.{}", namespace, child));
+        }
+    }
+    children.into_iter().collect()
+}
+
+/// A view of the facts below a namespace, with keys relative to it.
+pub struct ScopedFacts<'a> {
+    facts: &'a dyn FactSource,
+    scope: String,
+}
+
+impl<'a> ScopedFacts<'a> {
+    // Constructor for ScopedFacts
+    pub fn new(facts: &'a dyn FactSource, scope: String) -> Self {
+        ScopedFacts { facts, scope }
+    }
+
+    fn scoped(&self, key: &str) -> String {
+        format!("{}.{}", self.scope, key)
+    }
+}
+
+impl FactSource for ScopedFacts<'_> {
+    fn fact(&self, key: &str) -> Option<&Fact> {
+        self.facts.fact(&self.scoped(key))
+    }
+
+    fn keys_under(&self, namespace: &str) -> Vec<&str> {
+        self.facts
+            .keys_under(&self.scoped(namespace))
+            .into_iter()
+            .map(|key| &key[self.scope.len() + 1..])
+            .collect()
+    }
+
+    fn timeline(&self, key: &str) -> Option<&FactTimeline> {
+        self.facts.timeline(&self.scoped(key))
+    }
+
+    fn now(&self) -> Duration {
+        self.facts.now()
+    }
+}
+
+impl CoolFactStore {
+    // Keys in the namespace in sorted order, looked up in the key index instead of
+    // scanning every fact
+    pub fn keys_in<'a>(&'a self, namespace: &str) -> impl Iterator<Item = &'a String> + 'a {
+        let descendants = self.index.range(format!("{}.", namespace)..format!("{}/", namespace));
+        self.index.get(namespace).into_iter().chain(descendants)
+    }
+
+    pub fn facts_in<'a>(&'a self, namespace: &str) -> impl Iterator<Item = &'a Fact> + 'a {
+        self.keys_in(namespace).filter_map(|key| self.facts.get(key))
+    }
+
+    // Remove every fact in the namespace, e.g. all of `level` on level change
+    pub fn remove_namespace(&mut self, namespace: &str) {
+        let keys: Vec<String> = self.keys_in(namespace).cloned().collect();
+        for key in keys {
+            self.remove_fact(&key);
+        }
+    }
+
+    // Reset every fact in the namespace to its declared default, removing the ones that
+    // have none and restoring declared ones that are missing
+    pub fn reset_namespace(&mut self, namespace: &str) {
+        let keys: Vec<String> = self.keys_in(namespace).cloned().collect();
+        for key in keys {
+            self.reset_fact(&key);
+        }
+        let missing: Vec<Fact> = self.defaults
+            .values()
+            .filter(|default| is_under(default.key(), namespace) && !self.facts.contains_key(default.key()))
+            .cloned()
+            .collect();
+        for default in missing {
+            self.store_fact(default, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Condition;
+
+    fn landed(namespace: &str) -> [Condition; 2] {
+        let condition = Box::new(Condition::BoolEquals { fact_name: "landed".to_string(), expected_value: true });
+        [
+            Condition::AnyUnder { namespace: namespace.to_string(), condition: condition.clone() },
+            Condition::AllUnder { namespace: namespace.to_string(), condition },
+        ]
+    }
+
+    #[test]
+    fn any_and_all_under() {
+        let mut store = CoolFactStore::new();
+        let [any, all] = landed("pods");
+        assert!(!any.evaluate(&store));
+        // Nothing under the namespace isn't everything under it
+        assert!(!all.evaluate(&store));
+
+        store.store_bool("pods.one.landed".to_string(), true);
+        store.store_bool("pods.two.landed".to_string(), false);
+        assert!(any.evaluate(&store));
+        assert!(!all.evaluate(&store));
+
+        store.store_bool("pods.two.landed".to_string(), true);
+        assert!(all.evaluate(&store));
+        assert_eq!(child_namespaces(&store, "pods"), ["pods.one", "pods.two"]);
+    }
+}
@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+
+use crate::CoolFactStore;
+
+/// The flow of the game. `Loading` resets the round and moves straight on to `Playing`, so
+/// starting and restarting a round go through the same path.
+#[derive(States, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum GameState {
+    #[default]
+    MainMenu,
+    Settings,
+    Loading,
+    Playing,
+    Paused,
+    GameOver,
+}
+
+/// Entities with this component are despawned as soon as the game enters a state that is
+/// not in the list.
+#[derive(Component)]
+pub struct StateScoped(pub Vec<GameState>);
+
+impl StateScoped {
+    // Keep the entity only while in `state`
+    pub fn new(state: GameState) -> Self {
+        StateScoped(vec![state])
+    }
+
+    // Keep the entity while in any of `states`, e.g. gameplay that survives pausing
+    pub fn during(states: &[GameState]) -> Self {
+        StateScoped(states.to_vec())
+    }
+}
+
+// The states a round lasts through, from the first frame of play to the game over screen
+pub const IN_ROUND: [GameState; 3] = [GameState::Playing, GameState::Paused, GameState::GameOver];
+
+// Despawn the scoped entities that don't belong in the state that was just entered
+pub fn despawn_out_of_state(
+    mut commands: Commands,
+    state: Res<State<GameState>>,
+    query: Query<(Entity, &StateScoped)>,
+) {
+    for (entity, scope) in query.iter() {
+        if !scope.0.contains(state.get()) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Start a new round in the named level. The `level` facts are reset and the new name is
+/// stored in `level.name`.
+#[derive(Event)]
+pub struct LoadLevel {
+    pub name: String,
+}
+
+pub fn load_level(
+    mut events: EventReader<LoadLevel>,
+    mut storage: ResMut<CoolFactStore>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if let Some(event) = events.read().last() {
+        storage.reset_namespace("level");
+        storage.store_string("level.name".to_string(), event.name.clone());
+        next_state.set(GameState::Loading);
+    }
+}
+
+pub fn finish_loading(mut next_state: ResMut<NextState<GameState>>) {
+    next_state.set(GameState::Playing);
+}
+
+// Freeze virtual time while paused, so fact expiry and time-based rules stop with the game
+pub fn pause_time(mut time: ResMut<Time<Virtual>>) {
+    time.pause();
+}
+
+pub fn unpause_time(mut time: ResMut<Time<Virtual>>) {
+    time.unpause();
+}
+
+// Pause with Escape or the gamepad's start button. Everything else is done from the menus.
+pub fn game_state_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let start_pressed = gamepads
+        .iter()
+        .any(|gamepad| gamepad_buttons.just_pressed(GamepadButton::new(gamepad, GamepadButtonType::Start)));
+    if keys.just_pressed(KeyCode::Escape) || start_pressed {
+        next_state.set(GameState::Paused);
+    }
+}
+
+pub trait GameStateAppExt {
+    // Reset every fact under `namespace` to its declared default when entering `state`
+    fn reset_facts_on_enter<S: States>(&mut self, state: S, namespace: &str) -> &mut Self;
+}
+
+impl GameStateAppExt for App {
+    fn reset_facts_on_enter<S: States>(&mut self, state: S, namespace: &str) -> &mut Self {
+        let namespace = namespace.to_string();
+        self.add_systems(OnEnter(state), move |mut storage: ResMut<CoolFactStore>| {
+            storage.reset_namespace(&namespace);
+        })
+    }
+}
@@ -0,0 +1,219 @@
+use bevy::prelude::*;
+use bevy::utils::hashbrown::HashSet;
+
+use crate::{CoolFactStore, FactRemoved, FactUpdated};
+
+enum TemplatePart {
+    Text(String),
+    Fact(String),
+}
+
+/// Text kept in sync with the global facts named in its template, e.g. `"Fuel: {player1.fuel}"`.
+/// It is only rewritten when one of those facts changes; missing facts show as `-`.
+#[derive(Component)]
+pub struct FactText {
+    parts: Vec<TemplatePart>,
+    keys: HashSet<String>,
+}
+
+impl FactText {
+    // Constructor for FactText. An unclosed `{` is kept as plain text.
+    pub fn new(template: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut keys = HashSet::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                break;
+            };
+            if start > 0 {
+                parts.push(TemplatePart::Text(rest[..start].to_string()));
+            }
+            let key = rest[start + 1..start + end].trim().to_string();
+            keys.insert(key.clone());
+            parts.push(TemplatePart::Fact(key));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Text(rest.to_string()));
+        }
+        FactText { parts, keys }
+    }
+
+    pub fn render(&self, storage: &CoolFactStore) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                TemplatePart::Text(text) => text.clone(),
+                TemplatePart::Fact(key) => storage
+                    .facts
+                    .get(key)
+                    .map(|fact| fact.value_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            })
+            .collect()
+    }
+}
+
+/// Sets the width of its node to how full an int or float fact is, from 0 up to `max`
+#[derive(Component)]
+pub struct FactBar {
+    key: String,
+    max: f32,
+}
+
+impl FactBar {
+    pub fn new(key: &str, max: f32) -> Self {
+        FactBar { key: key.to_string(), max }
+    }
+
+    // An empty bar when `max` isn't positive, which would otherwise divide by zero
+    pub fn fill_percent(&self, storage: &CoolFactStore) -> f32 {
+        if self.max <= 0.0 {
+            return 0.0;
+        }
+        let value = storage.facts.get(&self.key).and_then(|fact| fact.as_f32()).unwrap_or(0.0);
+        (value / self.max * 100.0).clamp(0.0, 100.0)
+    }
+}
+
+// Keys of the global facts that were updated or removed since the last read
+fn changed_keys(updated: &mut EventReader<FactUpdated>, removed: &mut EventReader<FactRemoved>) -> HashSet<String> {
+    let mut keys: HashSet<String> = updated
+        .read()
+        .filter(|event| event.entity.is_none())
+        .map(|event| event.fact.key().to_string())
+        .collect();
+    keys.extend(
+        removed
+            .read()
+            .filter(|event| event.entity.is_none())
+            .map(|event| event.key.clone()),
+    );
+    keys
+}
+
+pub fn update_fact_texts(
+    mut updated: EventReader<FactUpdated>,
+    mut removed: EventReader<FactRemoved>,
+    storage: Res<CoolFactStore>,
+    mut query: Query<(Ref<FactText>, &mut Text)>,
+) {
+    let changed = changed_keys(&mut updated, &mut removed);
+    for (fact_text, mut text) in query.iter_mut() {
+        if fact_text.is_added() || !fact_text.keys.is_disjoint(&changed) {
+            text.sections[0].value = fact_text.render(&storage);
+        }
+    }
+}
+
+pub fn update_fact_bars(
+    mut updated: EventReader<FactUpdated>,
+    mut removed: EventReader<FactRemoved>,
+    storage: Res<CoolFactStore>,
+    mut query: Query<(Ref<FactBar>, &mut Style)>,
+) {
+    let changed = changed_keys(&mut updated, &mut removed);
+    for (bar, mut style) in query.iter_mut() {
+        if bar.is_added() || changed.contains(&bar.key) {
+            style.width = Val::Percent(bar.fill_percent(&storage));
+        }
+    }
+}
+
+// A labelled bar for a fact, e.g. `fact_bar(builder, font, "Fuel", "player1.fuel", 100.0, Color::ORANGE)`
+pub fn fact_bar(builder: &mut ChildBuilder, font: Handle<Font>, label: &str, key: &str, max: f32, color: Color) {
+    builder
+        .spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(2.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|builder| {
+            builder.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font,
+                        font_size: 14.0,
+                        color: Color::BLACK,
+                    },
+                ),
+                FactText::new(&format!("{}: {{{}}}", label, key)),
+            ));
+            builder
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(100.0),
+                        height: Val::Px(10.0),
+                        ..default()
+                    },
+                    background_color: BackgroundColor(Color::DARK_GRAY),
+                    ..default()
+                })
+                .with_children(|builder| {
+                    builder.spawn((
+                        NodeBundle {
+                            style: Style {
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: BackgroundColor(color),
+                            ..default()
+                        },
+                        FactBar::new(key, max),
+                    ));
+                });
+        });
+}
+
+// One row of the HUD with the facts under the player's namespace
+pub fn spawn_player_hud(builder: &mut ChildBuilder, font: Handle<Font>, player: &str) {
+    builder
+        .spawn(NodeBundle {
+            style: Style {
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(16.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|builder| {
+            let text_style = TextStyle {
+                font: font.clone(),
+                font_size: 20.0,
+                color: Color::BLACK,
+            };
+            builder.spawn(TextBundle::from_section(player, text_style.clone()));
+            fact_bar(builder, font.clone(), "Fuel", &format!("{}.fuel", player), 100.0, Color::ORANGE);
+            fact_bar(builder, font.clone(), "Health", &format!("{}.health", player), 100.0, Color::CRIMSON);
+            fact_bar(builder, font.clone(), "Shield", &format!("{}.shield", player), 50.0, Color::CYAN);
+            builder.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                FactText::new(&format!("Lives: {{{}.lives}}", player)),
+            ));
+            builder.spawn((
+                TextBundle::from_section("", text_style),
+                FactText::new(&format!("Score: {{{}.score}}", player)),
+            ));
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_percent_is_clamped_and_empty_without_a_max() {
+        let mut storage = CoolFactStore::new();
+        storage.store_int("fuel".to_string(), 25);
+        assert_eq!(FactBar::new("fuel", 50.0).fill_percent(&storage), 50.0);
+        assert_eq!(FactBar::new("fuel", 10.0).fill_percent(&storage), 100.0);
+        assert_eq!(FactBar::new("missing", 50.0).fill_percent(&storage), 0.0);
+        assert_eq!(FactBar::new("fuel", 0.0).fill_percent(&storage), 0.0);
+        assert_eq!(FactBar::new("fuel", -5.0).fill_percent(&storage), 0.0);
+    }
+}
@@ -1,284 +1,254 @@
 #![allow(dead_code)]
 
+mod debug_inspector;
+mod dev_console;
+mod entity_facts;
+mod event_facts;
+mod fact_batch;
+mod fact_expr;
+mod fact_history;
+mod fact_mapping;
+mod fact_namespace;
+mod game_state;
+mod hud;
+mod menu;
+mod reflect_facts;
+mod rule_conditions;
+mod rule_explain;
+mod rule_network;
+mod rule_patterns;
+mod rule_policy;
+mod rule_templates;
+mod rule_timers;
+mod rule_validation;
+
+use std::collections::BTreeSet;
 use std::hash::{Hash, Hasher};
+use std::time::Duration;
+use bevy::app::StateTransition;
+use bevy::core::FrameCount;
+use bevy::ecs::system::RunSystemOnce;
 use bevy::prelude::*;
-use bevy::reflect::erased_serde::__private::serde::{Deserialize, Serialize};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use bevy::sprite::{MaterialMesh2dBundle, Mesh2dHandle};
 use bevy::utils::hashbrown::{HashMap, HashSet};
+use crate::debug_inspector::DebugInspector;
+use crate::dev_console::{console_closed, DevConsole};
+use crate::entity_facts::FactStore;
+use crate::event_facts::EventFactsAppExt;
+use crate::fact_expr::{Comparison, FactExpr};
+use crate::fact_history::{FactHistory, FactTimeline};
+use crate::fact_mapping::{FactMappingAppExt, Facts};
+use crate::fact_namespace::{child_namespaces, ScopedFacts};
+use crate::game_state::{GameState, GameStateAppExt, LoadLevel, StateScoped, IN_ROUND};
+use crate::menu::{MenuActivated, MenuButton, MenuSelection};
+use crate::reflect_facts::ReflectFactsAppExt;
+use crate::rule_conditions::{RuleListenerAppExt, RuleStatesAppExt};
+use crate::rule_network::RuleNetwork;
+use crate::rule_patterns::{Activation, Binding};
+use crate::rule_policy::{RuleFiring, RulePolicy};
+use crate::rule_templates::{RuleTemplate, TemplateInstance};
+use crate::rule_timers::{ConditionTimer, ConditionTimers, TimedEvaluation};
 
 const X_EXTENT: f32 = 600.;
 
 fn main() {
+    // `validate` checks the rules and stories, and `bench [rules] [changes]` compares the
+    // rule matchers, both without opening a window
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("validate") => std::process::exit(validate_content()),
+        Some("bench") => {
+            let number = |i: usize, default: usize| args.get(i).and_then(|arg| arg.parse().ok()).unwrap_or(default);
+            std::process::exit(rule_network::benchmark(number(1, 20_000), number(2, 100)));
+        }
+        _ => {}
+    }
+
     App::new()
         .insert_resource(Msaa::Sample4)
         .insert_resource(CoolFactStore::new())
         .insert_resource(RuleEngine::new())
         .insert_resource(StoryEngine::new())
         .add_event::<FactUpdated>()
+        .add_event::<FactRemoved>()
         .add_event::<RuleUpdated>()
         .add_plugins(DefaultPlugins)
-        .add_systems(Startup, setup)
-        .add_systems(Startup, spawn_layout)
-        .add_systems(Startup, setup_rules)
-        .add_systems(Update, button_system)
+        .init_state::<GameState>()
+        .add_systems(Startup, setup_camera)
+        .add_systems(Startup, (setup_players, setup_rules))
+        .add_systems(
+            StateTransition,
+            game_state::despawn_out_of_state.after(apply_state_transition::<GameState>),
+        )
+        .add_event::<MenuActivated>()
+        .init_resource::<MenuSelection>()
+        .add_systems(OnEnter(GameState::MainMenu), menu::spawn_main_menu)
+        .add_systems(OnEnter(GameState::Settings), menu::spawn_settings_menu)
+        .add_systems(OnEnter(GameState::Loading), game_state::finish_loading)
+        .reset_facts_on_enter(GameState::Loading, "button_pressed")
+        .reset_facts_on_enter(GameState::Loading, "recently_pressed")
+        .add_systems(OnEnter(GameState::Loading), reset_players)
+        .transition_on_rule("game_over_rule", GameState::GameOver)
+        .on_rule_activated("out_of_fuel_rule", lose_life.run_if(in_state(GameState::Playing)))
+        .add_systems(
+            OnTransition { from: GameState::Loading, to: GameState::Playing },
+            (setup, spawn_layout),
+        )
+        .add_systems(OnEnter(GameState::Paused), (game_state::pause_time, menu::spawn_pause_menu))
+        .add_systems(OnExit(GameState::Paused), game_state::unpause_time)
+        .add_systems(OnEnter(GameState::GameOver), menu::spawn_game_over_menu)
+        .add_event::<LoadLevel>()
+        .add_systems(Update, game_state::load_level)
+        .add_systems(
+            Update,
+            game_state::game_state_input.run_if(in_state(GameState::Playing)).run_if(console_closed),
+        )
+        .add_systems(
+            Update,
+            (menu::menu_mouse, menu::menu_navigation, menu::style_menu_buttons, menu::menu_actions)
+                .chain()
+                .run_if(any_with_component::<MenuButton>)
+                .run_if(console_closed),
+        )
+        .count_event_as_fact::<ButtonPressed>("button_pressed")
+        .map_event_to_fact::<ButtonPressed>(|_, storage| {
+            storage.store_fact(
+                Fact::Bool("recently_pressed".to_string(), true),
+                Some(Duration::from_secs(1)),
+            );
+        })
+        .sync_component_facts::<ShipStats>()
+        .bridge_filtered_component_facts::<Transform, With<ShipStats>>(&[("translation.y", "altitude")])
+        .add_systems(PreUpdate, (fact_clock_system, fact_expiry_system).chain())
+        .add_systems(Update, button_system.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, burn_fuel.run_if(in_state(GameState::Playing)).run_if(console_closed))
         .add_systems(Update, fact_update_event_broadcaster)
-        .add_systems(Update, fact_event_system)
-        .add_systems(Update, rule_event_system)
+        .add_systems(Update, (hud::update_fact_texts, hud::update_fact_bars).after(fact_update_event_broadcaster))
+        .add_systems(Update, entity_facts::entity_fact_update_event_broadcaster)
+        .init_resource::<DebugInspector>()
+        .add_systems(Startup, debug_inspector::spawn_debug_overlay)
+        .add_systems(Update, debug_inspector::log_fact_events)
+        .add_systems(Update, debug_inspector::log_rule_events)
+        .add_systems(
+            Update,
+            (
+                debug_inspector::debug_inspector_input.run_if(console_closed),
+                debug_inspector::render_debug_overlay,
+            )
+                .chain(),
+        )
+        .init_resource::<DevConsole>()
+        .add_systems(Startup, dev_console::spawn_console)
+        .add_systems(Update, (dev_console::console_input, dev_console::render_console).chain())
+        .add_systems(Update, dev_console::print_watched_facts)
         .add_systems(Update, rule_evaluator)
+        .add_systems(Update, entity_facts::entity_rule_evaluator)
+        .add_systems(Update, entity_facts::forget_removed_fact_stores)
+        .add_systems(Update, rule_patterns::pattern_rule_evaluator)
         .run();
 }
 
+// `entity` is set when the fact lives in an entity's `FactStore` instead of the global store
 #[derive(Event)]
 pub struct FactUpdated {
     fact: Fact,
+    entity: Option<Entity>,
 }
 
+#[derive(Event)]
+pub struct FactRemoved {
+    key: String,
+    entity: Option<Entity>,
+}
+
+// `entity` is set for per-entity rules, evaluated against that entity's `FactStore`, and
+// for pattern rules, where it is the entity bound to the first variable and `bindings` has
+// all of them. `triggered_by` holds the changed facts the rule reads, sorted, and is empty
+// when the rule changed because time passed or the rules themselves changed.
 #[derive(Event)]
 pub struct RuleUpdated {
     rule: String,
+    entity: Option<Entity>,
+    new_state: bool,
+    triggered_by: Vec<String>,
+    frame: u32,
+    bindings: Binding,
 }
 
 fn fact_update_event_broadcaster(
     mut event_writer: EventWriter<FactUpdated>,
+    mut removed_writer: EventWriter<FactRemoved>,
     mut storage: ResMut<CoolFactStore>,
 ) {
     for fact in storage.updated_facts.drain() {
         event_writer.send(FactUpdated {
-            fact
+            fact,
+            entity: None,
+        });
+    }
+    for key in storage.removed_facts.drain() {
+        removed_writer.send(FactRemoved {
+            key,
+            entity: None,
         });
     }
 }
 
-#[derive(Component)]
-pub struct TextComponent;
+fn fact_clock_system(
+    time: Res<Time>,
+    frame_count: Res<FrameCount>,
+    mut storage: ResMut<CoolFactStore>,
+    mut entity_stores: Query<&mut FactStore>,
+) {
+    storage.advance_clock(frame_count.0, time.elapsed());
+    for mut store in entity_stores.iter_mut() {
+        store.advance_clock(frame_count.0, time.elapsed());
+    }
+}
+
+fn fact_expiry_system(
+    mut storage: ResMut<CoolFactStore>,
+    mut entity_stores: Query<&mut FactStore>,
+) {
+    storage.expire_facts();
+    for mut store in entity_stores.iter_mut() {
+        store.expire_facts();
+    }
+}
 
 fn spawn_layout(mut commands: Commands, asset_server: Res<AssetServer>) {
     let font = asset_server.load("fonts/FiraSans-Bold.ttf");
-    // Top-level grid (app frame)
+    // Top-level frame, part of the round like the ships. It leaves the rest of the window
+    // to the game under the HUD header.
     commands
-        .spawn(NodeBundle {
+        .spawn((NodeBundle {
             style: Style {
-                // Use the CSS Grid algorithm for laying out this node
-                display: Display::Grid,
-                // Make node fill the entirety it's parent (in this case the window)
                 width: Val::Percent(100.0),
                 height: Val::Percent(100.0),
-                // Set the grid to have 2 columns with sizes [min-content, minmax(0, 1fr)]
-                //   - The first column will size to the size of it's contents
-                //   - The second column will take up the remaining available space
-                grid_template_columns: vec![GridTrack::min_content(), GridTrack::flex(1.0)],
-                // Set the grid to have 3 rows with sizes [auto, minmax(0, 1fr), 20px]
-                //  - The first row will size to the size of it's contents
-                //  - The second row take up remaining available space (after rows 1 and 3 have both been sized)
-                //  - The third row will be exactly 20px high
-                grid_template_rows: vec![
-                    GridTrack::auto(),
-                    GridTrack::flex(1.0),
-                    GridTrack::px(20.),
-                ],
+                flex_direction: FlexDirection::Column,
                 ..default()
             },
-            background_color: BackgroundColor(Color::WHITE),
             ..default()
-        })
+        }, StateScoped::during(&IN_ROUND)))
         .with_children(|builder| {
             // Header
             builder
                 .spawn(NodeBundle {
                     style: Style {
-                        display: Display::Grid,
-                        // Make this node span two grid columns so that it takes up the entire top tow
-                        grid_column: GridPlacement::span(2),
+                        flex_direction: FlexDirection::Column,
                         padding: UiRect::all(Val::Px(6.0)),
                         ..default()
                     },
+                    background_color: BackgroundColor(Color::WHITE),
                     ..default()
                 })
                 .with_children(|builder| {
-                    spawn_nested_text_bundle(builder, font.clone(), "Bevy CSS Grid Layout Example");
+                    for player in PLAYERS {
+                        hud::spawn_player_hud(builder, font.clone(), player);
+                    }
                 });
-
-            // Main content grid (auto placed in row 2, column 1)
-            builder
-                .spawn(NodeBundle {
-                    style: Style {
-                        // Make the height of the node fill its parent
-                        height: Val::Percent(100.0),
-                        // Make the grid have a 1:1 aspect ratio meaning it will scale as an exact square
-                        // As the height is set explicitly, this means the width will adjust to match the height
-                        aspect_ratio: Some(1.0),
-                        // Use grid layout for this node
-                        display: Display::Grid,
-                        // Add 24px of padding around the grid
-                        padding: UiRect::all(Val::Px(24.0)),
-                        // Set the grid to have 4 columns all with sizes minmax(0, 1fr)
-                        // This creates 4 exactly evenly sized columns
-                        grid_template_columns: RepeatedGridTrack::flex(4, 1.0),
-                        // Set the grid to have 4 rows all with sizes minmax(0, 1fr)
-                        // This creates 4 exactly evenly sized rows
-                        grid_template_rows: RepeatedGridTrack::flex(4, 1.0),
-                        // Set a 12px gap/gutter between rows and columns
-                        row_gap: Val::Px(12.0),
-                        column_gap: Val::Px(12.0),
-                        ..default()
-                    },
-                    background_color: BackgroundColor(Color::DARK_GRAY),
-                    ..default()
-                })
-                .with_children(|builder| {
-                    // Note there is no need to specify the position for each grid item. Grid items that are
-                    // not given an explicit position will be automatically positioned into the next available
-                    // grid cell. The order in which this is performed can be controlled using the grid_auto_flow
-                    // style property.
-
-                    item_rect(builder, Color::ORANGE, false, font.clone_weak());
-                    item_rect(builder, Color::BISQUE, false, font.clone_weak());
-                    item_rect(builder, Color::BLUE, false, font.clone_weak());
-                    item_rect(builder, Color::CRIMSON, false, font.clone_weak());
-
-                    item_rect(builder, Color::CYAN, false, font.clone_weak());
-                    item_rect(builder, Color::ORANGE_RED, false, font.clone_weak());
-                    item_rect(builder, Color::DARK_GREEN, false, font.clone_weak());
-                    item_rect(builder, Color::FUCHSIA, false, font.clone_weak());
-
-                    item_rect(builder, Color::TEAL, false, font.clone_weak());
-                    item_rect(builder, Color::ALICE_BLUE, false, font.clone_weak());
-                    item_rect(builder, Color::CRIMSON, false, font.clone_weak());
-                    item_rect(builder, Color::ANTIQUE_WHITE, false, font.clone_weak());
-
-                    item_rect(builder, Color::YELLOW, false, font.clone_weak());
-                    item_rect(builder, Color::PINK, false, font.clone_weak());
-                    item_rect(builder, Color::YELLOW_GREEN, false, font.clone_weak());
-                    item_rect(builder, Color::SALMON, true, font.clone_weak());
-                });
-
-            // Right side bar (auto placed in row 2, column 2)
-            builder
-                .spawn(NodeBundle {
-                    style: Style {
-                        display: Display::Grid,
-                        // Align content towards the start (top) in the vertical axis
-                        align_items: AlignItems::Start,
-                        // Align content towards the center in the horizontal axis
-                        justify_items: JustifyItems::Center,
-                        // Add 10px padding
-                        padding: UiRect::all(Val::Px(10.)),
-                        // Add an fr track to take up all the available space at the bottom of the column so that the text nodes
-                        // can be top-aligned. Normally you'd use flexbox for this, but this is the CSS Grid example so we're using grid.
-                        grid_template_rows: vec![GridTrack::auto(), GridTrack::auto(), GridTrack::fr(1.0)],
-                        // Add a 10px gap between rows
-                        row_gap: Val::Px(10.),
-                        ..default()
-                    },
-                    background_color: BackgroundColor(Color::BLACK),
-                    ..default()
-                })
-                .with_children(|builder| {
-                    builder.spawn(TextBundle::from_section(
-                        "Sidebar",
-                        TextStyle {
-                            font: font.clone(),
-                            font_size: 24.0,
-                            ..default()
-                        },
-                    ));
-                    builder.spawn((TextBundle::from_section(
-                        "A paragraph of text which ought to wrap nicely. A paragraph of text which ought to wrap nicely. A paragraph of text which ought to wrap nicely. A paragraph of text which ought to wrap nicely. A paragraph of text which ought to wrap nicely. A paragraph of text which ought to wrap nicely. A paragraph of text which ought to wrap nicely.",
-                        TextStyle {
-                            font: font.clone(),
-                            font_size: 16.0,
-                            ..default()
-                        },
-                    ), TextComponent
-                    ));
-                    builder.spawn(NodeBundle::default());
-                });
-
-            // Footer / status bar
-            builder.spawn(NodeBundle {
-                style: Style {
-                    // Make this node span two grid column so that it takes up the entire bottom row
-                    grid_column: GridPlacement::span(2),
-                    ..default()
-                },
-                background_color: BackgroundColor(Color::WHITE),
-                ..default()
-            });
-
-            // Modal (absolutely positioned on top of content - currently hidden: to view it, change its visibility)
-            builder.spawn(NodeBundle {
-                visibility: Visibility::Hidden,
-                style: Style {
-                    position_type: PositionType::Absolute,
-                    margin: UiRect {
-                        top: Val::Px(100.),
-                        bottom: Val::Auto,
-                        left: Val::Auto,
-                        right: Val::Auto,
-                    },
-                    width: Val::Percent(60.),
-                    height: Val::Px(300.),
-                    max_width: Val::Px(600.),
-                    ..default()
-                },
-                background_color: BackgroundColor(Color::Rgba {
-                    red: 255.0,
-                    green: 255.0,
-                    blue: 255.0,
-                    alpha: 0.8,
-                }),
-                ..default()
-            });
-        });
-}
-
-/// Create a coloured rectangle node. The node has size as it is assumed that it will be
-/// spawned as a child of a Grid container with `AlignItems::Stretch` and `JustifyItems::Stretch`
-/// which will allow it to take it's size from the size of the grid area it occupies.
-fn item_rect(builder: &mut ChildBuilder, color: Color, with_button: bool, font: Handle<Font>) {
-    builder
-        .spawn(NodeBundle {
-            style: Style {
-                display: Display::Grid,
-                padding: UiRect::all(Val::Px(3.0)),
-                ..default()
-            },
-            background_color: BackgroundColor(Color::BLACK),
-            ..default()
-        })
-        .with_children(|builder| {
-            if with_button {
-                builder.spawn(ButtonBundle {
-                    style: Style {
-                        width: Val::Px(150.0),
-                        height: Val::Px(65.0),
-                        border: UiRect::all(Val::Px(5.0)),
-                        // horizontally center child text
-                        justify_content: JustifyContent::Center,
-                        // vertically center child text
-                        align_items: AlignItems::Center,
-                        ..default()
-                    },
-                    border_color: BorderColor(Color::BLACK),
-                    background_color: NORMAL_BUTTON.into(),
-                    ..default()
-                })
-                    .with_children(|parent| {
-                        parent.spawn(TextBundle::from_section(
-                            "Button",
-                            TextStyle {
-                                font,
-                                font_size: 40.0,
-                                color: Color::rgb(0.9, 0.9, 0.9),
-                            },
-                        ));
-                    });
-            }
-
-            builder.spawn(NodeBundle {
-                background_color: BackgroundColor(color),
-                ..default()
-            });
         });
 }
 
@@ -297,65 +267,76 @@ const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
 const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
 const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.75, 0.35);
 
-fn fact_event_system(
-    mut query: Query<&mut Text, With<TextComponent>>,
-    mut fact_update_events: EventReader<FactUpdated>,
-) {
-    for event in fact_update_events.read() {
-        for mut text in query.iter_mut() {
-            text.sections[0].value = format!("{}\n{:?}", text.sections[0].value, event.fact);
-        }
-    }
-}
-
-fn rule_event_system(
-    mut query: Query<&mut Text, With<TextComponent>>,
-    mut rule_updated_events: EventReader<RuleUpdated>,
-) {
-    for event in rule_updated_events.read() {
-        for mut text in query.iter_mut() {
-            text.sections[0].value = format!("{}\n{:?}", text.sections[0].value, event.rule);
-        }
-    }
-}
+type ButtonInteractionQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Interaction,
+        &'static mut BackgroundColor,
+        &'static mut BorderColor,
+        &'static Children,
+    ),
+    (Changed<Interaction>, With<Button>, Without<MenuButton>),
+>;
 
+#[derive(Event)]
+pub struct ButtonPressed;
 
 fn button_system(
-    mut interaction_query: Query<
-        (
-            &Interaction,
-            &mut BackgroundColor,
-            &mut BorderColor,
-            &Children,
-        ),
-        (Changed<Interaction>, With<Button>),
-    >,
+    mut interaction_query: ButtonInteractionQuery,
     mut text_query: Query<&mut Text>,
-    mut storage: ResMut<CoolFactStore>,
+    mut button_pressed: EventWriter<ButtonPressed>,
+    storage: Res<CoolFactStore>,
 ) {
     for (interaction, mut color, mut border_color, children) in &mut interaction_query {
         let mut text = text_query.get_mut(children[0]).unwrap();
+        style_button(*interaction, &mut color, &mut border_color);
         match *interaction {
             Interaction::Pressed => {
-                storage.add_to_int("button_pressed".to_string(), 1);
+                button_pressed.send(ButtonPressed);
                 text.sections[0].value = "Press".to_string();
-                *color = PRESSED_BUTTON.into();
-                border_color.0 = Color::RED;
             }
             Interaction::Hovered => {
                 text.sections[0].value = storage.get_int("button_pressed").unwrap_or(&0).to_string();
-                *color = HOVERED_BUTTON.into();
-                border_color.0 = Color::WHITE;
             }
             Interaction::None => {
                 text.sections[0].value = "Press to add".to_string();
-                *color = NORMAL_BUTTON.into();
-                border_color.0 = Color::BLACK;
             }
         }
     }
 }
 
+// Colour a button for its interaction. Menus pass `Hovered` for the button that has focus
+// so keyboard and gamepad selection looks the same as mouse hover.
+pub fn style_button(interaction: Interaction, color: &mut BackgroundColor, border_color: &mut BorderColor) {
+    let (background, border) = match interaction {
+        Interaction::Pressed => (PRESSED_BUTTON, Color::RED),
+        Interaction::Hovered => (HOVERED_BUTTON, Color::WHITE),
+        Interaction::None => (NORMAL_BUTTON, Color::BLACK),
+    };
+    *color = background.into();
+    border_color.0 = border;
+}
+
+// A button with centered content in its resting colours
+pub fn button_bundle(width: Val, height: Val) -> ButtonBundle {
+    ButtonBundle {
+        style: Style {
+            width,
+            height,
+            border: UiRect::all(Val::Px(5.0)),
+            // horizontally center child text
+            justify_content: JustifyContent::Center,
+            // vertically center child text
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        border_color: BorderColor(Color::BLACK),
+        background_color: NORMAL_BUTTON.into(),
+        ..default()
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Deserialize, Serialize)]
 pub struct StringHashSet(HashSet<String>);
 
@@ -371,6 +352,47 @@ impl StringHashSet {
     fn remove(&mut self, value: &String) -> bool {
         self.0.remove(value)
     }
+
+    fn iter(&self) -> impl Iterator<Item = &String> {
+        self.0.iter()
+    }
+
+    // The values in order, for showing them
+    fn sorted(&self) -> Vec<&String> {
+        let mut values: Vec<&String> = self.0.iter().collect();
+        values.sort();
+        values
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn contains(&self, value: &str) -> bool {
+        self.0.contains(value)
+    }
+
+    fn is_subset(&self, other: &StringHashSet) -> bool {
+        self.0.is_subset(&other.0)
+    }
+
+    fn is_superset(&self, other: &StringHashSet) -> bool {
+        self.0.is_superset(&other.0)
+    }
+
+    fn intersects(&self, other: &StringHashSet) -> bool {
+        !self.0.is_disjoint(&other.0)
+    }
+}
+
+impl<S: Into<String>> FromIterator<S> for StringHashSet {
+    fn from_iter<I: IntoIterator<Item = S>>(values: I) -> Self {
+        StringHashSet(values.into_iter().map(Into::into).collect())
+    }
 }
 
 impl Hash for StringHashSet {
@@ -383,21 +405,217 @@ impl Hash for StringHashSet {
     }
 }
 
+// An f32 that can be hashed and compared for equality by its bits, so float facts fit
+// in the same sets and maps as every other fact
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct FloatValue(pub f32);
+
+impl PartialEq for FloatValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for FloatValue {}
+
+impl Hash for FloatValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+// A compiled regex that is compared, hashed and saved as its pattern, so conditions that
+// match against it can still be compared, hashed and saved
+#[derive(Debug, Clone)]
+pub struct RegexPattern(pub Regex);
+
+impl RegexPattern {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(RegexPattern)
+    }
+}
+
+impl PartialEq for RegexPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for RegexPattern {}
+
+impl Hash for RegexPattern {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state);
+    }
+}
+
+impl Serialize for RegexPattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for RegexPattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        RegexPattern::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum Fact {
     Int(String, i32),
     String(String, String),
     Bool(String, bool),
     StringList(String, StringHashSet),
+    Float(String, FloatValue),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum FactKind {
+    Int,
+    String,
+    Bool,
+    StringList,
+    Float,
+}
+
+impl Fact {
+    pub fn key(&self) -> &str {
+        match self {
+            Fact::Int(key, _)
+            | Fact::String(key, _)
+            | Fact::Bool(key, _)
+            | Fact::StringList(key, _)
+            | Fact::Float(key, _) => key,
+        }
+    }
+
+    pub fn kind(&self) -> FactKind {
+        match self {
+            Fact::Int(..) => FactKind::Int,
+            Fact::String(..) => FactKind::String,
+            Fact::Bool(..) => FactKind::Bool,
+            Fact::StringList(..) => FactKind::StringList,
+            Fact::Float(..) => FactKind::Float,
+        }
+    }
+
+    // The value without the key, as shown to players. Lists are sorted so the text is stable.
+    pub fn value_string(&self) -> String {
+        match self {
+            Fact::Int(_, value) => value.to_string(),
+            Fact::String(_, value) => value.clone(),
+            Fact::Bool(_, value) => value.to_string(),
+            Fact::StringList(_, values) => {
+                values.sorted().into_iter().cloned().collect::<Vec<_>>().join(", ")
+            }
+            Fact::Float(_, value) => format!("{:.1}", value.0),
+        }
+    }
+
+    // The value of a numeric fact
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            Fact::Int(_, value) => Some(*value as f32),
+            Fact::Float(_, value) => Some(value.0),
+            _ => None,
+        }
+    }
+}
+
+/// Values that can be turned into a fact with a given key.
+pub trait IntoFact {
+    fn into_fact(self, key: String) -> Fact;
+}
+
+impl IntoFact for i32 {
+    fn into_fact(self, key: String) -> Fact {
+        Fact::Int(key, self)
+    }
+}
+
+impl IntoFact for f32 {
+    fn into_fact(self, key: String) -> Fact {
+        Fact::Float(key, FloatValue(self))
+    }
+}
+
+impl IntoFact for bool {
+    fn into_fact(self, key: String) -> Fact {
+        Fact::Bool(key, self)
+    }
+}
+
+impl IntoFact for String {
+    fn into_fact(self, key: String) -> Fact {
+        Fact::String(key, self)
+    }
+}
+
+impl IntoFact for &str {
+    fn into_fact(self, key: String) -> Fact {
+        Fact::String(key, self.to_string())
+    }
+}
+
+impl IntoFact for StringHashSet {
+    fn into_fact(self, key: String) -> Fact {
+        Fact::StringList(key, self)
+    }
+}
+
+/// Read access to facts for evaluating conditions. A plain fact map only knows current values;
+/// `CoolFactStore` also provides fact history and the current time for time-windowed conditions.
+pub trait FactSource {
+    fn fact(&self, key: &str) -> Option<&Fact>;
+
+    // Keys of the facts in a namespace, including the namespace key itself
+    fn keys_under(&self, namespace: &str) -> Vec<&str>;
+
+    fn timeline(&self, _key: &str) -> Option<&FactTimeline> {
+        None
+    }
+
+    fn now(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+impl FactSource for HashMap<String, Fact> {
+    fn fact(&self, key: &str) -> Option<&Fact> {
+        self.get(key)
+    }
+
+    fn keys_under(&self, namespace: &str) -> Vec<&str> {
+        let mut keys: Vec<&str> = self.keys()
+            .map(String::as_str)
+            .filter(|key| fact_namespace::is_under(key, namespace))
+            .collect();
+        keys.sort();
+        keys
+    }
 }
 
 
+// Stored in the ship's own `FactStore`, so the facts are just `fuel` and `landed`
+#[derive(Component, Facts)]
+#[facts(prefix = "")]
+pub struct ShipStats {
+    pub fuel: i32,
+    pub landed: bool,
+}
+
+fn setup_camera(mut commands: Commands) {
+    commands.spawn(Camera2dBundle::default());
+}
+
+// Spawn the ships for a new round. They stay around while paused and on the game over screen.
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    commands.spawn(Camera2dBundle::default());
 
     let shapes = [
         Mesh2dHandle(meshes.add(Triangle2d::new(
@@ -412,7 +630,7 @@ fn setup(
         // Distribute colors evenly across the rainbow.
         let color = Color::hsl(360. * i as f32 / num_shapes as f32, 0.95, 0.7);
 
-        commands.spawn(MaterialMesh2dBundle {
+        commands.spawn((MaterialMesh2dBundle {
             mesh: shape,
             material: materials.add(color),
             transform: Transform::from_xyz(
@@ -422,14 +640,21 @@ fn setup(
                 0.0,
             ),
             ..default()
-        });
+        }, ShipStats { fuel: 100, landed: false }, FactStore::new(), StateScoped::during(&IN_ROUND)));
     }
 }
 
 #[derive(Resource, Deserialize, Serialize)]
-struct CoolFactStore {
+pub struct CoolFactStore {
     facts: HashMap<String, Fact>,
+    index: BTreeSet<String>,
     updated_facts: HashSet<Fact>,
+    removed_facts: HashSet<String>,
+    defaults: HashMap<String, Fact>,
+    expirations: HashMap<String, Duration>,
+    history: FactHistory,
+    frame: u32,
+    elapsed: Duration,
 }
 
 
@@ -438,24 +663,159 @@ impl CoolFactStore {
     fn new() -> Self {
         CoolFactStore {
             facts: HashMap::new(),
+            index: BTreeSet::new(),
             updated_facts: HashSet::new(),
+            removed_facts: HashSet::new(),
+            defaults: HashMap::new(),
+            expirations: HashMap::new(),
+            history: FactHistory::new(),
+            frame: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    // Keep the last `capacity` changes of a fact. Its current value is kept as where the
+    // history starts, but doesn't count as a change.
+    fn track_history(&mut self, key: String, capacity: usize) {
+        self.history.track(key.clone(), capacity);
+        if let Some(fact) = self.facts.get(&key) {
+            self.history.seed(fact, self.frame, self.elapsed);
+        }
+    }
+
+    fn history(&self) -> &FactHistory {
+        &self.history
+    }
+
+    // Update the frame and elapsed time that new changes are stamped with
+    fn advance_clock(&mut self, frame: u32, elapsed: Duration) {
+        self.frame = frame;
+        self.elapsed = elapsed;
+    }
+
+    // Add a fact that is not in the store yet
+    fn insert_fact(&mut self, fact: Fact) {
+        self.index.insert(fact.key().to_string());
+        self.facts.insert(fact.key().to_string(), fact.clone());
+        self.mark_updated(fact);
+    }
+
+    fn mark_updated(&mut self, fact: Fact) {
+        self.history.record(&fact, self.frame, self.elapsed);
+        self.removed_facts.remove(fact.key());
+        self.updated_facts.insert(fact);
+    }
+
+    // How many times a tracked fact changed in the last `window`
+    fn changes_within(&self, key: &str, window: Duration) -> usize {
+        self.history.changes_within(key, window, self.elapsed)
+    }
+
+    fn time_since_last_change(&self, key: &str) -> Option<Duration> {
+        self.history.time_since_last_change(key, self.elapsed)
+    }
+
+    // The value a tracked fact had at the given elapsed time
+    fn value_at(&self, key: &str, at: Duration) -> Option<&Fact> {
+        self.history.value_at(key, at)
+    }
+
+    // When a tracked fact last held the given value, e.g. when `landed` was last true
+    fn last_held(&self, fact: &Fact) -> Option<Duration> {
+        self.history.last_held(fact, self.elapsed)
+    }
+
+    // Declare a fact with the value it is reset to, e.g. when a temporary fact expires
+    fn declare_fact(&mut self, default: Fact) {
+        self.defaults.insert(default.key().to_string(), default);
+    }
+
+    fn declared_default(&self, key: &str) -> Option<&Fact> {
+        self.defaults.get(key)
+    }
+
+    // Store any kind of fact, optionally expiring it after `ttl`. Storing it again
+    // with a ttl restarts the countdown.
+    fn store_fact(&mut self, fact: Fact, ttl: Option<Duration>) {
+        if let Some(ttl) = ttl {
+            self.expire_after(fact.key(), ttl);
+        }
+        match fact {
+            Fact::Int(key, value) => self.store_int(key, value),
+            Fact::String(key, value) => self.store_string(key, value),
+            Fact::Bool(key, value) => self.store_bool(key, value),
+            Fact::StringList(key, value) => self.store_list(key, value),
+            Fact::Float(key, value) => self.store_float(key, value.0),
+        }
+    }
+
+    // Expire an existing or soon to be stored fact after `ttl` from now
+    fn expire_after(&mut self, key: &str, ttl: Duration) {
+        self.expirations.insert(key.to_string(), self.elapsed + ttl);
+    }
+
+    // Make a fact permanent again
+    fn cancel_expiry(&mut self, key: &str) {
+        self.expirations.remove(key);
+    }
+
+    fn time_to_live(&self, key: &str) -> Option<Duration> {
+        self.expirations
+            .get(key)
+            .map(|expires_at| expires_at.saturating_sub(self.elapsed))
+    }
+
+    // Reset every fact whose ttl has run out to its declared default, or remove it
+    // if it has none
+    fn expire_facts(&mut self) {
+        let now = self.elapsed;
+        let expired: Vec<String> = self.expirations
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.expirations.remove(&key);
+            self.reset_fact(&key);
         }
     }
 
+    // Reset a fact to its declared default, or remove it if it has none
+    fn reset_fact(&mut self, key: &str) {
+        self.expirations.remove(key);
+        if let Some(default) = self.defaults.get(key).cloned() {
+            self.store_fact(default, None);
+        } else {
+            self.remove_fact(key);
+        }
+    }
+
+    fn remove_fact(&mut self, key: &str) -> Option<Fact> {
+        self.expirations.remove(key);
+        let removed = self.facts.remove(key);
+        if removed.is_some() {
+            self.index.remove(key);
+            self.updated_facts.retain(|fact| fact.key() != key);
+            self.history.record_removal(key, self.frame, self.elapsed);
+            self.removed_facts.insert(key.to_string());
+        }
+        removed
+    }
+
     // Store an integer fact
     fn store_int(&mut self, key: String, value: i32) {
         if let Some(fact) = self.facts.get_mut(&key) {
             if let Fact::Int(_, current_value) = fact {
                 if current_value != &value {
                     *fact = Fact::Int(key.clone(), value);
-                    self.updated_facts.insert(fact.clone());
+                    let fact = fact.clone();
+                    self.mark_updated(fact);
                 }
             } else {
                 panic!("Fact with key {} is not an integer", key)
             }
         } else {
-            self.facts.insert(key.clone(), Fact::Int(key.clone(), value));
-            self.updated_facts.insert(Fact::Int(key.clone(), value));
+            self.insert_fact(Fact::Int(key.clone(), value));
         }
     }
 
@@ -469,20 +829,37 @@ impl CoolFactStore {
         self.store_int(key, current + value);
     }
 
+    // Store a float fact
+    fn store_float(&mut self, key: String, value: f32) {
+        if let Some(fact) = self.facts.get_mut(&key) {
+            if let Fact::Float(_, current_value) = fact {
+                if current_value != &FloatValue(value) {
+                    *fact = Fact::Float(key.clone(), FloatValue(value));
+                    let fact = fact.clone();
+                    self.mark_updated(fact);
+                }
+            } else {
+                panic!("Fact with key {} is not a float", key)
+            }
+        } else {
+            self.insert_fact(Fact::Float(key.clone(), FloatValue(value)));
+        }
+    }
+
     // Store a string fact
     fn store_string(&mut self, key: String, value: String) {
         if let Some(fact) = self.facts.get_mut(&key) {
             if let Fact::String(_, current_value) = fact {
                 if current_value != &value {
                     *fact = Fact::String(key.clone(), value.clone());
-                    self.updated_facts.insert(fact.clone());
+                    let fact = fact.clone();
+                    self.mark_updated(fact);
                 }
             } else {
                 panic!("Fact with key {} is not a string", key)
             }
         } else {
-            self.facts.insert(key.clone(), Fact::String(key.clone(), value.clone()));
-            self.updated_facts.insert(Fact::String(key.clone(), value.clone()));
+            self.insert_fact(Fact::String(key.clone(), value));
         }
     }
 
@@ -492,14 +869,14 @@ impl CoolFactStore {
             if let Fact::Bool(_, current_value) = fact {
                 if current_value != &value {
                     *fact = Fact::Bool(key.clone(), value);
-                    self.updated_facts.insert(fact.clone());
+                    let fact = fact.clone();
+                    self.mark_updated(fact);
                 }
             } else {
                 panic!("Fact with key {} is not a boolean", key)
             }
         } else {
-            self.facts.insert(key.clone(), Fact::Bool(key.clone(), value.clone()));
-            self.updated_facts.insert(Fact::Bool(key.clone(), value.clone()));
+            self.insert_fact(Fact::Bool(key.clone(), value));
         }
     }
 
@@ -508,14 +885,31 @@ impl CoolFactStore {
         if let Some(list_fact) = self.facts.get_mut(&key) {
             if let Fact::StringList(_, list) = list_fact {
                 if list.insert(value) {
-                    self.updated_facts.insert(list_fact.clone());
+                    let list_fact = list_fact.clone();
+                    self.mark_updated(list_fact);
                 }
             }
         } else {
             let mut new_list = StringHashSet::new();
             new_list.insert(value);
-            self.facts.insert(key.clone(), Fact::StringList(key.clone(), new_list.clone()));
-            self.updated_facts.insert(Fact::StringList(key.clone(), new_list.clone()));
+            self.insert_fact(Fact::StringList(key.clone(), new_list));
+        }
+    }
+
+    // Replace a list of strings fact
+    fn store_list(&mut self, key: String, value: StringHashSet) {
+        if let Some(fact) = self.facts.get_mut(&key) {
+            if let Fact::StringList(_, current_value) = fact {
+                if current_value != &value {
+                    *fact = Fact::StringList(key.clone(), value);
+                    let fact = fact.clone();
+                    self.mark_updated(fact);
+                }
+            } else {
+                panic!("Fact with key {} is not a list", key)
+            }
+        } else {
+            self.insert_fact(Fact::StringList(key.clone(), value));
         }
     }
 
@@ -523,7 +917,8 @@ impl CoolFactStore {
         if let Some(list_fact) = self.facts.get_mut(&key) {
             if let Fact::StringList(_, list) = list_fact {
                 if list.remove(&value) {
-                    self.updated_facts.insert(list_fact.clone());
+                    let list_fact = list_fact.clone();
+                    self.mark_updated(list_fact);
                 }
             }
         }
@@ -531,38 +926,65 @@ impl CoolFactStore {
 
     // Retrieve an integer fact
     fn get_int(&self, key: &str) -> Option<&i32> {
-        return if let Some(Fact::Int(_, value)) = self.facts.get(key) {
-            Some(&value)
+        if let Some(Fact::Int(_, value)) = self.facts.get(key) {
+            Some(value)
         } else {
             None
-        };
+        }
+    }
+
+    // Retrieve a float fact
+    fn get_float(&self, key: &str) -> Option<&f32> {
+        if let Some(Fact::Float(_, value)) = self.facts.get(key) {
+            Some(&value.0)
+        } else {
+            None
+        }
     }
 
     // Retrieve a string fact
     fn get_string(&self, key: &str) -> Option<&String> {
-        return if let Some(Fact::String(_, value)) = self.facts.get(key) {
-            Some(&value)
+        if let Some(Fact::String(_, value)) = self.facts.get(key) {
+            Some(value)
         } else {
             None
-        };
+        }
     }
 
     // Retrieve a boolean fact
     fn get_bool(&self, key: &str) -> Option<&bool> {
-        return if let Some(Fact::Bool(_, value)) = self.facts.get(key) {
-            Some(&value)
+        if let Some(Fact::Bool(_, value)) = self.facts.get(key) {
+            Some(value)
         } else {
             None
-        };
+        }
     }
 
     // Retrieve a list of strings fact
     fn get_list(&self, key: &str) -> Option<&StringHashSet> {
-        return if let Some(Fact::StringList(_, value)) = self.facts.get(key) {
-            Some(&value)
+        if let Some(Fact::StringList(_, value)) = self.facts.get(key) {
+            Some(value)
         } else {
             None
-        };
+        }
+    }
+}
+
+impl FactSource for CoolFactStore {
+    fn fact(&self, key: &str) -> Option<&Fact> {
+        self.facts.get(key)
+    }
+
+    fn keys_under(&self, namespace: &str) -> Vec<&str> {
+        self.keys_in(namespace).map(String::as_str).collect()
+    }
+
+    fn timeline(&self, key: &str) -> Option<&FactTimeline> {
+        self.history.timeline(key)
+    }
+
+    fn now(&self) -> Duration {
+        self.elapsed
     }
 }
 
@@ -570,6 +992,68 @@ impl CoolFactStore {
 pub struct RuleEngine {
     rules: HashMap<String, Rule>,
     rule_states: HashMap<String, bool>,
+    // How often rules may fire, and how often the global ones have fired so far
+    #[serde(default)]
+    policies: HashMap<String, RulePolicy>,
+    #[serde(default)]
+    firings: HashMap<String, RuleFiring>,
+    // Rules written once with parameters, see `RuleTemplate`
+    #[serde(default)]
+    templates: HashMap<String, RuleTemplate>,
+    // Entities don't survive a save and load, so their rule states aren't saved
+    #[serde(skip)]
+    entity_rule_states: HashMap<Entity, HashMap<String, bool>>,
+    #[serde(skip)]
+    entity_firings: HashMap<Entity, HashMap<String, RuleFiring>>,
+    // Disabled rules, and rules in disabled groups, are inactive whatever the facts say
+    #[serde(default)]
+    disabled_rules: HashSet<String>,
+    #[serde(default)]
+    disabled_groups: HashSet<String>,
+    // Bumped whenever rules are added, replaced or removed, which recompiles the network
+    #[serde(skip)]
+    revision: u64,
+    // Bumped whenever rules are enabled, disabled or get another policy. What they match
+    // stays the same, so only their states are worked out again.
+    #[serde(skip)]
+    settings_revision: u64,
+    // Global rules whose settings changed, for the next evaluation to look at
+    #[serde(skip)]
+    unsettled_rules: HashSet<String>,
+    // Names passed to `add_rule` more than once, for the validator to report
+    #[serde(skip)]
+    duplicate_rules: Vec<String>,
+    // Timers of duration conditions, which start over after a load
+    #[serde(skip)]
+    timers: ConditionTimers,
+    #[serde(skip)]
+    entity_timers: HashMap<Entity, ConditionTimers>,
+    // When each global rule has to be evaluated again because time passed, and the same
+    // for the earliest per-entity rule of each entity
+    #[serde(skip)]
+    wake_ups: HashMap<String, Duration>,
+    #[serde(skip)]
+    entity_wake_ups: HashMap<Entity, Duration>,
+    // Deactivations that didn't come from evaluating, for `rule_evaluator` to send
+    #[serde(skip)]
+    deactivated: Vec<(String, Option<Entity>, Binding)>,
+    // Pattern rules by the bindings of their variables, and when one has to be evaluated
+    // again because time passed
+    #[serde(skip)]
+    pattern_activations: HashMap<String, HashMap<Binding, Activation>>,
+    #[serde(skip)]
+    pattern_wake_ups: HashMap<String, Duration>,
+    // Global rules are matched with a compiled network instead, see `set_use_network`
+    #[serde(default)]
+    use_network: bool,
+    #[serde(skip)]
+    network: Option<RuleNetwork>,
+}
+
+impl Default for RuleEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RuleEngine {
@@ -578,33 +1062,388 @@ impl RuleEngine {
         RuleEngine {
             rules: HashMap::new(),
             rule_states: HashMap::new(),
+            policies: HashMap::new(),
+            firings: HashMap::new(),
+            templates: HashMap::new(),
+            entity_rule_states: HashMap::new(),
+            entity_firings: HashMap::new(),
+            disabled_rules: HashSet::new(),
+            disabled_groups: HashSet::new(),
+            revision: 0,
+            settings_revision: 0,
+            unsettled_rules: HashSet::new(),
+            duplicate_rules: Vec::new(),
+            timers: ConditionTimers::new(),
+            entity_timers: HashMap::new(),
+            wake_ups: HashMap::new(),
+            entity_wake_ups: HashMap::new(),
+            deactivated: Vec::new(),
+            pattern_activations: HashMap::new(),
+            pattern_wake_ups: HashMap::new(),
+            use_network: false,
+            network: None,
         }
     }
 
-    // Add a new rule to the rule engine
+    // Add a new rule to the rule engine. A rule with the same name is replaced, but that is
+    // reported as a mistake; use `replace_rule` to swap a rule on purpose.
     pub fn add_rule(&mut self, rule: Rule) {
+        if self.rules.contains_key(&rule.name) {
+            warn!("Rule {} was added twice, replacing the first one", rule.name);
+            self.duplicate_rules.push(rule.name.clone());
+        }
+        self.replace_rule(rule);
+    }
+
+    // Add a rule, or replace the one with the same name, which starts over as inactive. Its
+    // policy and how often it has fired are kept. Returns the replaced rule.
+    pub fn replace_rule(&mut self, rule: Rule) -> Option<Rule> {
+        self.reset_rule(&rule.name);
         self.rule_states.insert(rule.name.clone(), false);
-        self.rules.insert(rule.name.clone(), rule);
+        self.revision += 1;
+        self.rules.insert(rule.name.clone(), rule)
     }
 
-    // Evaluate all rules based on the provided facts
-    pub fn evaluate_rules(&mut self, facts: &HashMap<String, Fact>) -> HashSet<String> {
-        let mut updated_rule_states = HashSet::new();
+    // Remove a rule, deactivating it wherever it was active
+    pub fn remove_rule(&mut self, rule: &str) -> Option<Rule> {
+        let removed = self.rules.remove(rule)?;
+        self.reset_rule(rule);
+        self.rule_states.remove(rule);
+        self.disabled_rules.remove(rule);
+        self.policies.remove(rule);
+        self.firings.remove(rule);
+        for firings in self.entity_firings.values_mut() {
+            firings.remove(rule);
+        }
+        self.revision += 1;
+        Some(removed)
+    }
+
+    // Enable or disable a rule, returns false if there is no such rule. Disabling resets it.
+    pub fn set_enabled(&mut self, rule: &str, enabled: bool) -> bool {
+        if !self.rules.contains_key(rule) {
+            return false;
+        }
+        if enabled {
+            self.disabled_rules.remove(rule);
+        } else {
+            self.disabled_rules.insert(rule.to_string());
+            self.reset_rule(rule);
+        }
+        self.settings_changed(rule);
+        true
+    }
+
+    // Enable or disable every rule in the group, returns false if no rule is in it. A rule
+    // runs only if it and all of its groups are enabled.
+    pub fn set_group_enabled(&mut self, group: &str, enabled: bool) -> bool {
+        let mut members: Vec<String> = self
+            .rules
+            .values()
+            .filter(|rule| rule.groups.iter().any(|g| g == group))
+            .map(|rule| rule.name.clone())
+            .collect();
+        if members.is_empty() {
+            return false;
+        }
+        members.sort();
+        if enabled {
+            self.disabled_groups.remove(group);
+        } else {
+            self.disabled_groups.insert(group.to_string());
+            for rule in &members {
+                self.reset_rule(rule);
+            }
+        }
+        for rule in &members {
+            self.settings_changed(rule);
+        }
+        true
+    }
+
+    pub fn is_enabled(&self, rule: &str) -> bool {
         self.rules
+            .get(rule)
+            .is_some_and(|rule| rule_enabled(rule, &self.disabled_rules, &self.disabled_groups))
+    }
+
+    pub fn is_group_enabled(&self, group: &str) -> bool {
+        !self.disabled_groups.contains(group)
+    }
+
+    // Every group any rule is in, sorted
+    pub fn groups(&self) -> Vec<&str> {
+        let mut groups: Vec<&str> = self
+            .rules
+            .values()
+            .flat_map(|rule| rule.groups.iter().map(String::as_str))
+            .collect();
+        groups.sort();
+        groups.dedup();
+        groups
+    }
+
+    // Put a rule back to how it was when added: inactive everywhere, with no timers running.
+    // A deactivation is queued for the global store, then for each entity in entity order,
+    // then for each binding of a pattern rule in binding order, wherever it was active.
+    fn reset_rule(&mut self, rule: &str) {
+        let pattern = self.rules.get(rule).is_some_and(Rule::is_pattern);
+        if self.rule_states.get(rule) == Some(&true) {
+            self.rule_states.insert(rule.to_string(), false);
+            if !pattern {
+                self.deactivated.push((rule.to_string(), None, Vec::new()));
+            }
+        }
+        let mut entities: Vec<Entity> = self
+            .entity_rule_states
+            .iter_mut()
+            .filter_map(|(entity, states)| (states.remove(rule) == Some(true)).then_some(*entity))
+            .collect();
+        entities.sort();
+        self.deactivated.extend(entities.into_iter().map(|entity| (rule.to_string(), Some(entity), Vec::new())));
+        let mut bindings: Vec<Binding> = self
+            .pattern_activations
+            .remove(rule)
+            .into_iter()
+            .flatten()
+            .filter_map(|(binding, activation)| activation.is_active().then_some(binding))
+            .collect();
+        bindings.sort();
+        for binding in bindings {
+            let entity = binding.first().map(|(_, entity)| *entity);
+            self.deactivated.push((rule.to_string(), entity, binding));
+        }
+
+        let prefix = format!("{}:", rule);
+        self.timers.retain(|key, _| !key.starts_with(&prefix));
+        for timers in self.entity_timers.values_mut() {
+            timers.retain(|key, _| !key.starts_with(&prefix));
+        }
+        self.wake_ups.remove(rule);
+        self.pattern_wake_ups.remove(rule);
+    }
+
+    // Rules that were deactivated by being disabled, replaced or removed since the last call
+    pub fn take_deactivated(&mut self) -> Vec<(String, Option<Entity>, Binding)> {
+        std::mem::take(&mut self.deactivated)
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn settings_revision(&self) -> u64 {
+        self.settings_revision
+    }
+
+    // Have the rule's state worked out again, without recompiling anything
+    fn settings_changed(&mut self, rule: &str) {
+        self.settings_revision += 1;
+        if self.rules.get(rule).is_some_and(|rule| !rule.per_entity && !rule.is_pattern()) {
+            self.unsettled_rules.insert(rule.to_string());
+        }
+    }
+
+    pub fn duplicate_rules(&self) -> &[String] {
+        &self.duplicate_rules
+    }
+
+    // Evaluate all global rules based on the provided facts
+    pub fn evaluate_rules(&mut self, facts: &dyn FactSource) -> HashSet<String> {
+        let global_rules = self.rules.values().filter(|rule| !rule.per_entity && !rule.is_pattern());
+        self.network = self.use_network.then(|| {
+            let mut network = RuleNetwork::compile(global_rules.clone(), self.revision);
+            network.evaluate_all(facts);
+            network
+        });
+        let names: Vec<String> = global_rules.map(|rule| rule.name.clone()).collect();
+        self.unsettled_rules.clear();
+        self.evaluate_named_rules(names, facts)
+    }
+
+    // Evaluate only the global rules that read one of the changed facts, whose wake-up time
+    // has come, or that were enabled, disabled or got another policy. With the network, only
+    // the rules it says changed are looked at for the facts.
+    pub fn evaluate_rules_affected_by(&mut self, changed: &HashSet<String>, facts: &dyn FactSource) -> HashSet<String> {
+        if self.use_network && self.network.as_ref().map(RuleNetwork::revision) != Some(self.revision) {
+            return self.evaluate_rules(facts);
+        }
+        let now = facts.now();
+        let mut names: HashSet<String> = self
+            .wake_ups
             .iter()
-            .for_each(|(name, rule)| {
-                let previous_state = self.rule_states.get(name).unwrap();
-                if previous_state != &rule.evaluate(facts) {
-                    self.rule_states.insert(name.clone(), !previous_state);
-                    updated_rule_states.insert(name.clone());
+            .filter(|(_, at)| **at <= now)
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.extend(self.unsettled_rules.drain());
+        let reads_changed = |rule: &Rule| {
+            !rule.per_entity && !rule.is_pattern() && changed.iter().any(|key| rule.reads_fact(key))
+        };
+        match &mut self.network {
+            Some(network) => {
+                names.extend(network.update(changed, facts));
+                let uncompiled = network.uncompiled().iter().filter(|name| self.rules.get(*name).is_some_and(reads_changed));
+                names.extend(uncompiled.cloned());
+            }
+            None => names.extend(self.rules.values().filter(|rule| reads_changed(rule)).map(|rule| rule.name.clone())),
+        }
+        self.evaluate_named_rules(names, facts)
+    }
+
+    fn evaluate_named_rules(&mut self, names: impl IntoIterator<Item = String>, facts: &dyn FactSource) -> HashSet<String> {
+        let mut updated_rule_states = HashSet::new();
+        for name in names {
+            let Some(rule) = self.rules.get(&name) else {
+                continue;
+            };
+            let name = &name;
+            let mut timed = TimedEvaluation::new(&mut self.timers, facts.now());
+            // Compiled rules have no timers, so the network's answer is all there is to know
+            let compiled = self.network.as_ref().and_then(|network| network.holds(name));
+            let holds = rule_enabled(rule, &self.disabled_rules, &self.disabled_groups)
+                && compiled.unwrap_or_else(|| timed.rule(rule, facts));
+            let previous_state = *self.rule_states.get(name).unwrap();
+            let (state, policy_wake_up) = match self.policies.get(name) {
+                Some(policy) => {
+                    let firing = self.firings.entry(name.clone()).or_default();
+                    firing.apply(policy, holds, previous_state, facts.now())
                 }
-            });
-        return updated_rule_states;
+                None => (holds, None),
+            };
+            match timed.wake_up().into_iter().chain(policy_wake_up).min() {
+                Some(at) => self.wake_ups.insert(name.clone(), at),
+                None => self.wake_ups.remove(name),
+            };
+            if previous_state != state {
+                self.rule_states.insert(name.clone(), state);
+                updated_rule_states.insert(name.clone());
+            }
+        }
+        updated_rule_states
+    }
+
+    // The earliest time a global rule has to be evaluated again even if no fact changes
+    pub fn next_wake_up(&self) -> Option<Duration> {
+        self.wake_ups.values().min().copied()
+    }
+
+    // The timers of a global rule's duration conditions, keyed by their position in the rule
+    pub fn timer(&self, key: &str) -> Option<&ConditionTimer> {
+        self.timers.get(key)
+    }
+
+    pub fn rule(&self, name: &str) -> Option<&Rule> {
+        self.rules.get(name)
+    }
+
+    // The changed facts that the rule reads, sorted
+    pub fn facts_read_by(&self, rule: &str, changed: &HashSet<String>) -> Vec<String> {
+        let Some(rule) = self.rules.get(rule) else {
+            return Vec::new();
+        };
+        let mut facts: Vec<String> = changed.iter().filter(|key| rule.reads_fact(key)).cloned().collect();
+        facts.sort();
+        facts
+    }
+
+    // Whether the global rule is currently active
+    pub fn is_active(&self, rule: &str) -> bool {
+        self.rule_states.get(rule).copied().unwrap_or(false)
+    }
+
+    // Every global rule with its current state
+    pub fn rule_states(&self) -> impl Iterator<Item = (&String, bool)> {
+        self.rule_states.iter().map(|(name, state)| (name, *state))
+    }
+}
+
+// Whether a rule is enabled, both on its own and through every group it is in
+fn rule_enabled(rule: &Rule, disabled_rules: &HashSet<String>, disabled_groups: &HashSet<String>) -> bool {
+    !disabled_rules.contains(&rule.name) && !rule.groups.iter().any(|group| disabled_groups.contains(group))
+}
+
+// Run the startup systems that declare facts and add rules and stories, then print what the
+// validator finds. Returns the exit code, 1 if there are errors.
+fn validate_content() -> i32 {
+    let mut world = World::new();
+    world.insert_resource(CoolFactStore::new());
+    world.insert_resource(RuleEngine::new());
+    world.insert_resource(StoryEngine::new());
+    world.run_system_once(setup_players);
+    world.run_system_once(setup_rules);
+    world.run_system_once(setup_stories);
+
+    let diagnostics = rule_validation::validate(
+        world.resource::<RuleEngine>(),
+        world.resource::<StoryEngine>(),
+        world.resource::<CoolFactStore>(),
+    );
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+    let errors = diagnostics.iter().filter(|d| d.severity == rule_validation::Severity::Error).count();
+    println!("{} problems, {} errors", diagnostics.len(), errors);
+    if errors > 0 { 1 } else { 0 }
+}
+
+// Namespaces of the players' facts, e.g. `player1.fuel`
+const PLAYERS: [&str; 1] = ["player1"];
+
+// Declare the facts every player starts a round with
+fn setup_players(mut storage: ResMut<CoolFactStore>) {
+    for player in PLAYERS {
+        storage.declare_fact(Fact::Int(format!("{}.fuel", player), 100));
+        storage.declare_fact(Fact::Int(format!("{}.health", player), 100));
+        storage.declare_fact(Fact::Int(format!("{}.shield", player), 50));
+        storage.declare_fact(Fact::Int(format!("{}.lives", player), 3));
+        storage.declare_fact(Fact::Int(format!("{}.score", player), 0));
+    }
+}
+
+fn reset_players(mut storage: ResMut<CoolFactStore>) {
+    for player in PLAYERS {
+        storage.reset_namespace(player);
+    }
+}
+
+// Fuel each player's thrusters burn per second while space is held
+const FUEL_BURN_RATE: f32 = 20.0;
+
+// Holding space fires the thrusters, which burns the players' fuel down to empty. Whole
+// units are taken off as they are used up, so the facts only change a few times a second.
+fn burn_fuel(
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    mut burnt: Local<f32>,
+    mut storage: ResMut<CoolFactStore>,
+) {
+    if !keys.pressed(KeyCode::Space) {
+        return;
+    }
+    *burnt += FUEL_BURN_RATE * time.delta_seconds();
+    let units = burnt.floor();
+    if units < 1.0 {
+        return;
+    }
+    *burnt -= units;
+    for player in PLAYERS {
+        let key = format!("{}.fuel", player);
+        let fuel = storage.get_int(&key).copied().unwrap_or(0);
+        if fuel > 0 {
+            storage.store_int(key, (fuel - units as i32).max(0));
+        }
     }
 }
 
+// Running dry for too long costs a life, and the next one starts with a full tank
+fn lose_life(mut storage: ResMut<CoolFactStore>) {
+    storage.add_to_int("player1.lives".to_string(), -1);
+    storage.reset_fact("player1.fuel");
+}
+
 fn setup_rules(
     mut rule_engine: ResMut<RuleEngine>,
+    mut storage: ResMut<CoolFactStore>,
 ) {
     let rule1 = Rule::new(
         "button_pressed_rule".to_string(),
@@ -614,24 +1453,143 @@ fn setup_rules(
     );
 
     rule_engine.add_rule(rule1);
+
+    storage.declare_fact(Fact::Int("button_pressed".to_string(), 0));
+    storage.declare_fact(Fact::Bool("recently_pressed".to_string(), false));
+    storage.track_history("button_pressed".to_string(), 16);
+    let rule2 = Rule::new(
+        "button_mashed_rule".to_string(),
+        vec![
+            Condition::ChangeCountAtLeast {
+                fact_name: "button_pressed".to_string(),
+                count: 3,
+                within: Duration::from_secs(2),
+            },
+        ],
+    );
+
+    rule_engine.add_rule(rule2);
+    // Mashing is reported at most every ten seconds
+    rule_engine.set_policy("button_mashed_rule", RulePolicy::default().cooldown(Duration::from_secs(10)));
+
+    // Ships keep `fuel` in their own store, see `ShipStats`
+    let rule3 = Rule::new(
+        "low_fuel_rule".to_string(),
+        vec![
+            Condition::IntLessThan { fact_name: "fuel".to_string(), expected_value: 10 },
+        ],
+    ).per_entity();
+
+    rule_engine.add_rule(rule3);
+
+    let game_over_rule = Rule::new(
+        "game_over_rule".to_string(),
+        vec![
+            Condition::IntLessThan { fact_name: "player1.lives".to_string(), expected_value: 1 },
+        ],
+    );
+
+    rule_engine.add_rule(game_over_rule);
+
+    // Stranded after five seconds without fuel
+    let out_of_fuel_rule = Rule::new(
+        "out_of_fuel_rule".to_string(),
+        vec![
+            Condition::IntLessThan { fact_name: "player1.fuel".to_string(), expected_value: 1 }
+                .held_for(Duration::from_secs(5)),
+        ],
+    );
+
+    rule_engine.add_rule(out_of_fuel_rule);
+
+    // One activation for each ship running low, carrying the ship
+    let ship_low_fuel_rule = Rule::new(
+        "ship_low_fuel_rule".to_string(),
+        vec![
+            Condition::IntLessThan { fact_name: "?ship.fuel".to_string(), expected_value: 10 },
+        ],
+    );
+
+    rule_engine.add_rule(ship_low_fuel_rule);
+
+    let all_ships_landed_rule = Rule::new(
+        "all_ships_landed_rule".to_string(),
+        vec![
+            Condition::BoolEquals { fact_name: "?ship.landed".to_string(), expected_value: true },
+        ],
+    ).for_all("?ship");
+
+    rule_engine.add_rule(all_ships_landed_rule);
+
+    // Warn each player a while before the fuel runs out, `low_fuel("player1","20")` and so on
+    let low_fuel = RuleTemplate::new(
+        "low_fuel",
+        &["player", "threshold"],
+        r#"(name: "low_fuel", conditions: [IntLessThan(fact_name: "$player.fuel", expected_value: $threshold)])"#,
+    );
+    rule_engine.add_template(low_fuel).expect("low_fuel only uses its own parameters");
+    for player in PLAYERS {
+        let instance = TemplateInstance::new("low_fuel", &[("player", player), ("threshold", "20")]);
+        if let Err(error) = rule_engine.add_instance(&instance) {
+            warn!("{}", error);
+        }
+    }
 }
 
 fn rule_evaluator(
     mut rules: ResMut<RuleEngine>,
     mut fact_updated: EventReader<FactUpdated>,
+    mut fact_removed: EventReader<FactRemoved>,
     mut rule_updated_writer: EventWriter<RuleUpdated>,
     storage: Res<CoolFactStore>,
+    mut seen_revision: Local<(u64, u64)>,
 ) {
-    // we obviously only update when facts are updated. In future, only update rules
-    // that are affected by the updated facts
-    for _ in fact_updated.read() {
-        let facts = &storage.facts;
-        let results = rules.evaluate_rules(facts);
-        for rule_name in results {
-            rule_updated_writer.send(RuleUpdated {
-                rule: rule_name.clone()
-            });
-        }
+    // we obviously only update when facts are updated, when the rules themselves or their
+    // settings change, or when a rule asked to be woken up because time passing can change
+    // it. Only the rules that read one of the changed facts, are due or had their settings
+    // changed are evaluated, unless the rules themselves changed.
+    let mut changed: HashSet<String> = fact_updated
+        .read()
+        .filter(|event| event.entity.is_none())
+        .map(|event| event.fact.key().to_string())
+        .collect();
+    changed.extend(
+        fact_removed
+            .read()
+            .filter(|event| event.entity.is_none())
+            .map(|event| event.key.clone()),
+    );
+    for (rule, entity, bindings) in rules.take_deactivated() {
+        rule_updated_writer.send(RuleUpdated {
+            rule,
+            entity,
+            new_state: false,
+            triggered_by: Vec::new(),
+            frame: storage.frame,
+            bindings,
+        });
+    }
+    let rules_changed = seen_revision.0 != rules.revision();
+    let settings_changed = seen_revision.1 != rules.settings_revision();
+    *seen_revision = (rules.revision(), rules.settings_revision());
+    let woken = rules.next_wake_up().is_some_and(|at| at <= storage.now());
+    if changed.is_empty() && !rules_changed && !settings_changed && !woken {
+        return;
+    }
+    let results = if rules_changed {
+        rules.evaluate_rules(&*storage)
+    } else {
+        rules.evaluate_rules_affected_by(&changed, &*storage)
+    };
+    for rule_name in results {
+        rule_updated_writer.send(RuleUpdated {
+            new_state: rules.is_active(&rule_name),
+            triggered_by: rules.facts_read_by(&rule_name, &changed),
+            rule: rule_name,
+            entity: None,
+            frame: storage.frame,
+            bindings: Vec::new(),
+        });
     }
 }
 
@@ -643,51 +1601,233 @@ pub enum Condition {
     StringEquals { fact_name: String, expected_value: String },
     BoolEquals { fact_name: String, expected_value: bool },
     ListContains { fact_name: String, expected_value: String },
+    StringStartsWith { fact_name: String, prefix: String },
+    StringEndsWith { fact_name: String, suffix: String },
+    StringContains { fact_name: String, expected_value: String },
+    StringEqualsIgnoreCase { fact_name: String, expected_value: String },
+    StringMatches { fact_name: String, pattern: RegexPattern },
+    // True if the string fact is one of the values
+    StringIn { fact_name: String, values: StringHashSet },
+    ListSize { fact_name: String, comparison: Comparison, size: usize },
+    // True if every item of the list is one of the values
+    ListSubsetOf { fact_name: String, values: StringHashSet },
+    // True if the list has every one of the values
+    ListSupersetOf { fact_name: String, values: StringHashSet },
+    // True if the list has at least one of the values
+    ListIntersects { fact_name: String, values: StringHashSet },
+    FloatMoreThan { fact_name: String, expected_value: FloatValue },
+    FloatLessThan { fact_name: String, expected_value: FloatValue },
+    // True if the fact changed at least once in the last `within`
+    ChangedWithin { fact_name: String, within: Duration },
+    // True if the fact changed at least `count` times in the last `within`
+    ChangeCountAtLeast { fact_name: String, count: usize, within: Duration },
+    // True if the fact has not changed for at least `duration`, or at all since it was tracked
+    UnchangedFor { fact_name: String, duration: Duration },
+    // True if there is at least one fact in the namespace
+    HasFactsUnder { namespace: String },
+    // True if the condition holds for any direct child of the namespace, with its fact names
+    // relative to that child: `players` + `fuel` checks `players.one.fuel`, `players.two.fuel`...
+    AnyUnder { namespace: String, condition: Box<Condition> },
+    // Like `AnyUnder`, but the condition has to hold for every child of the namespace. False
+    // when the namespace has no children, so all pods aren't delivered before there are any.
+    AllUnder { namespace: String, condition: Box<Condition> },
+    // Compare two expressions over facts, e.g. `player1.score > player2.score` or
+    // `kills - deaths >= 10`. False if either side can't be computed.
+    Compare { left: FactExpr, comparison: Comparison, right: FactExpr },
+    // True once the condition has held for `duration` without a break, e.g. no fuel for
+    // five seconds. Duration conditions keep timers, which only the `RuleEngine` has, so
+    // they are false anywhere else.
+    HeldFor { condition: Box<Condition>, duration: Duration },
+    // True once the condition hasn't held for `duration`, counted from when the rule was
+    // first evaluated if it never held
+    NotWithin { condition: Box<Condition>, duration: Duration },
 }
 
 impl Condition {
     // Evaluate the condition based on the provided facts
-    pub fn evaluate(&self, facts: &HashMap<String, Fact>) -> bool {
+    pub fn evaluate(&self, facts: &dyn FactSource) -> bool {
         match self {
             Condition::IntEquals { fact_name, expected_value } => {
-                if let Some(Fact::Int(_, value)) = facts.get(fact_name) {
+                if let Some(Fact::Int(_, value)) = facts.fact(fact_name) {
                     return *value == *expected_value;
                 }
             }
             Condition::StringEquals { fact_name, expected_value } => {
-                if let Some(Fact::String(_, value)) = facts.get(fact_name) {
+                if let Some(Fact::String(_, value)) = facts.fact(fact_name) {
                     return value == expected_value;
                 }
             }
             Condition::BoolEquals { fact_name, expected_value } => {
-                if let Some(Fact::Bool(_, value)) = facts.get(fact_name) {
+                if let Some(Fact::Bool(_, value)) = facts.fact(fact_name) {
                     return *value == *expected_value;
                 }
             }
             Condition::IntMoreThan { fact_name, expected_value } => {
-                if let Some(Fact::Int(_, value)) = facts.get(fact_name) {
+                if let Some(Fact::Int(_, value)) = facts.fact(fact_name) {
                     return *value > *expected_value;
                 }
             }
             Condition::IntLessThan { fact_name, expected_value } => {
-                if let Some(Fact::Int(_, value)) = facts.get(fact_name) {
+                if let Some(Fact::Int(_, value)) = facts.fact(fact_name) {
                     return *value < *expected_value;
                 }
             }
             Condition::ListContains { fact_name, expected_value } => {
-                if let Some(Fact::StringList(_, value)) = facts.get(fact_name) {
-                    return value.0.contains(expected_value);
+                if let Some(Fact::StringList(_, value)) = facts.fact(fact_name) {
+                    return value.contains(expected_value);
+                }
+            }
+            Condition::StringStartsWith { fact_name, prefix } => {
+                if let Some(Fact::String(_, value)) = facts.fact(fact_name) {
+                    return value.starts_with(prefix.as_str());
+                }
+            }
+            Condition::StringEndsWith { fact_name, suffix } => {
+                if let Some(Fact::String(_, value)) = facts.fact(fact_name) {
+                    return value.ends_with(suffix.as_str());
+                }
+            }
+            Condition::StringContains { fact_name, expected_value } => {
+                if let Some(Fact::String(_, value)) = facts.fact(fact_name) {
+                    return value.contains(expected_value.as_str());
+                }
+            }
+            Condition::StringEqualsIgnoreCase { fact_name, expected_value } => {
+                if let Some(Fact::String(_, value)) = facts.fact(fact_name) {
+                    let expected = expected_value.chars().flat_map(char::to_lowercase);
+                    return value.chars().flat_map(char::to_lowercase).eq(expected);
                 }
             }
+            Condition::StringMatches { fact_name, pattern } => {
+                if let Some(Fact::String(_, value)) = facts.fact(fact_name) {
+                    return pattern.0.is_match(value);
+                }
+            }
+            Condition::StringIn { fact_name, values } => {
+                if let Some(Fact::String(_, value)) = facts.fact(fact_name) {
+                    return values.contains(value);
+                }
+            }
+            Condition::ListSize { fact_name, comparison, size } => {
+                if let Some(Fact::StringList(_, value)) = facts.fact(fact_name) {
+                    return comparison.compare(value.len() as f64, *size as f64);
+                }
+            }
+            Condition::ListSubsetOf { fact_name, values } => {
+                if let Some(Fact::StringList(_, value)) = facts.fact(fact_name) {
+                    return value.is_subset(values);
+                }
+            }
+            Condition::ListSupersetOf { fact_name, values } => {
+                if let Some(Fact::StringList(_, value)) = facts.fact(fact_name) {
+                    return value.is_superset(values);
+                }
+            }
+            Condition::ListIntersects { fact_name, values } => {
+                if let Some(Fact::StringList(_, value)) = facts.fact(fact_name) {
+                    return value.intersects(values);
+                }
+            }
+            Condition::FloatMoreThan { fact_name, expected_value } => {
+                if let Some(Fact::Float(_, value)) = facts.fact(fact_name) {
+                    return value.0 > expected_value.0;
+                }
+            }
+            Condition::FloatLessThan { fact_name, expected_value } => {
+                if let Some(Fact::Float(_, value)) = facts.fact(fact_name) {
+                    return value.0 < expected_value.0;
+                }
+            }
+            Condition::ChangedWithin { fact_name, within } => {
+                if let Some(timeline) = facts.timeline(fact_name) {
+                    return timeline.changes_within(*within, facts.now()) > 0;
+                }
+            }
+            Condition::ChangeCountAtLeast { fact_name, count, within } => {
+                if let Some(timeline) = facts.timeline(fact_name) {
+                    return timeline.changes_within(*within, facts.now()) >= *count;
+                }
+            }
+            Condition::UnchangedFor { fact_name, duration } => {
+                // A tracked fact that never changed has been unchanged all along
+                if let Some(timeline) = facts.timeline(fact_name) {
+                    return timeline.time_since_last_change(facts.now()).is_none_or(|since| since >= *duration);
+                }
+            }
+            Condition::HasFactsUnder { namespace } => {
+                return !facts.keys_under(namespace).is_empty();
+            }
+            Condition::AnyUnder { namespace, condition } => {
+                return child_namespaces(facts, namespace)
+                    .into_iter()
+                    .any(|child| condition.evaluate(&ScopedFacts::new(facts, child)));
+            }
+            Condition::AllUnder { namespace, condition } => {
+                let children = child_namespaces(facts, namespace);
+                return !children.is_empty()
+                    && children.into_iter().all(|child| condition.evaluate(&ScopedFacts::new(facts, child)));
+            }
+            Condition::Compare { left, comparison, right } => {
+                if let (Some(left), Some(right)) = (left.evaluate(facts), right.evaluate(facts)) {
+                    return comparison.compare(left, right);
+                }
+            }
+            Condition::HeldFor { .. } | Condition::NotWithin { .. } => {}
         }
         false
     }
+
+    // The facts the condition reads. Namespace conditions give their namespace, which
+    // stands for every fact under it.
+    pub fn fact_names(&self) -> Vec<&str> {
+        match self {
+            Condition::IntEquals { fact_name, .. }
+            | Condition::IntMoreThan { fact_name, .. }
+            | Condition::IntLessThan { fact_name, .. }
+            | Condition::StringEquals { fact_name, .. }
+            | Condition::BoolEquals { fact_name, .. }
+            | Condition::ListContains { fact_name, .. }
+            | Condition::StringStartsWith { fact_name, .. }
+            | Condition::StringEndsWith { fact_name, .. }
+            | Condition::StringContains { fact_name, .. }
+            | Condition::StringEqualsIgnoreCase { fact_name, .. }
+            | Condition::StringMatches { fact_name, .. }
+            | Condition::StringIn { fact_name, .. }
+            | Condition::ListSize { fact_name, .. }
+            | Condition::ListSubsetOf { fact_name, .. }
+            | Condition::ListSupersetOf { fact_name, .. }
+            | Condition::ListIntersects { fact_name, .. }
+            | Condition::FloatMoreThan { fact_name, .. }
+            | Condition::FloatLessThan { fact_name, .. }
+            | Condition::ChangedWithin { fact_name, .. }
+            | Condition::ChangeCountAtLeast { fact_name, .. }
+            | Condition::UnchangedFor { fact_name, .. } => vec![fact_name.as_str()],
+            Condition::HasFactsUnder { namespace }
+            | Condition::AnyUnder { namespace, .. }
+            | Condition::AllUnder { namespace, .. } => vec![namespace.as_str()],
+            Condition::Compare { left, right, .. } => {
+                let mut names = left.fact_names();
+                names.extend(right.fact_names());
+                names
+            }
+            Condition::HeldFor { condition, .. } | Condition::NotWithin { condition, .. } => condition.fact_names(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct Rule {
     pub name: String,
     pub conditions: Vec<Condition>,
+    // Evaluated once for every entity with a `FactStore` instead of against the global store
+    #[serde(default)]
+    pub per_entity: bool,
+    // Groups, such as a level or game mode, that can be switched on and off together
+    #[serde(default)]
+    pub groups: Vec<String>,
+    // Pattern variables the rule has to hold for with every entity, see `for_all`
+    #[serde(default)]
+    pub for_all: Vec<String>,
 }
 
 impl Rule {
@@ -696,13 +1836,35 @@ impl Rule {
         Rule {
             name,
             conditions,
+            per_entity: false,
+            groups: Vec::new(),
+            for_all: Vec::new(),
         }
     }
 
+    // Make this a rule that is evaluated for each entity with a `FactStore`
+    pub fn per_entity(mut self) -> Self {
+        self.per_entity = true;
+        self
+    }
+
+    pub fn in_group(mut self, group: &str) -> Self {
+        self.groups.push(group.to_string());
+        self
+    }
+
     // Evaluate all conditions for the rule based on the provided facts
-    pub fn evaluate(&self, facts: &HashMap<String, Fact>) -> bool {
+    pub fn evaluate(&self, facts: &dyn FactSource) -> bool {
         self.conditions.iter().all(|condition| condition.evaluate(facts))
     }
+
+    // Whether a change to `key` can change the outcome of the rule
+    pub fn reads_fact(&self, key: &str) -> bool {
+        self.conditions
+            .iter()
+            .flat_map(Condition::fact_names)
+            .any(|name| fact_namespace::is_under(key, name))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
@@ -723,7 +1885,7 @@ impl StoryBeat {
     }
 
     // Evaluate all rules for the story beat based on the provided facts
-    pub fn evaluate(&mut self, facts: &HashMap<String, Fact>) {
+    pub fn evaluate(&mut self, facts: &dyn FactSource) {
         self.finished = self.rules.iter().all(|rule| rule.evaluate(facts));
     }
 }
@@ -746,7 +1908,7 @@ impl Story {
     }
 
     // Evaluate the active story beat
-    pub fn evaluate_active_beat(&mut self, facts: &HashMap<String, Fact>) {
+    pub fn evaluate_active_beat(&mut self, facts: &dyn FactSource) {
         if self.active_beat_index < self.beats.len() {
             let active_beat = &mut self.beats[self.active_beat_index];
             active_beat.evaluate(facts);
@@ -756,6 +1918,19 @@ impl Story {
         }
     }
 
+    // Make the named beat the active one, so it and the beats after it have to be finished
+    // again. Returns false if the story has no such beat.
+    pub fn jump_to_beat(&mut self, beat: &str) -> bool {
+        let Some(index) = self.beats.iter().position(|b| b.name == beat) else {
+            return false;
+        };
+        self.active_beat_index = index;
+        for beat in &mut self.beats[index..] {
+            beat.finished = false;
+        }
+        true
+    }
+
     // Check if the story is finished
     pub fn is_finished(&self) -> bool {
         self.active_beat_index >= self.beats.len()
@@ -767,6 +1942,12 @@ pub struct StoryEngine {
     pub stories: Vec<Story>,
 }
 
+impl Default for StoryEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StoryEngine {
     // Constructor for StoryEngine
     pub fn new() -> Self {
@@ -781,7 +1962,7 @@ impl StoryEngine {
     }
 
     // Evaluate all stories based on the provided facts
-    pub fn evaluate_stories(&mut self, facts: &HashMap<String, Fact>) {
+    pub fn evaluate_stories(&mut self, facts: &dyn FactSource) {
         for story in &mut self.stories {
             story.evaluate_active_beat(facts);
         }
@@ -847,3 +2028,78 @@ fn setup_stories(
 
     story_engine.add_story(story);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(value: &str) -> CoolFactStore {
+        let mut storage = CoolFactStore::new();
+        storage.store_string("name".to_string(), value.to_string());
+        storage
+    }
+
+    fn list(values: &[&str]) -> CoolFactStore {
+        let mut storage = CoolFactStore::new();
+        storage.store_list("cargo".to_string(), values.iter().copied().collect());
+        storage
+    }
+
+    fn set(values: &[&str]) -> StringHashSet {
+        values.iter().copied().collect()
+    }
+
+    #[test]
+    fn string_conditions() {
+        let storage = string("Éclair Runner");
+        let holds = |condition: Condition| condition.evaluate(&storage);
+        let name = || "name".to_string();
+        let text = |text: &str| text.to_string();
+
+        assert!(holds(Condition::StringStartsWith { fact_name: name(), prefix: text("Éclair") }));
+        assert!(!holds(Condition::StringStartsWith { fact_name: name(), prefix: text("Runner") }));
+        assert!(holds(Condition::StringEndsWith { fact_name: name(), suffix: text("Runner") }));
+        assert!(!holds(Condition::StringEndsWith { fact_name: name(), suffix: text("Éclair") }));
+        assert!(holds(Condition::StringContains { fact_name: name(), expected_value: text("ir R") }));
+        assert!(!holds(Condition::StringContains { fact_name: name(), expected_value: text("runner") }));
+        assert!(holds(Condition::StringEqualsIgnoreCase { fact_name: name(), expected_value: text("éCLAIR rUNNER") }));
+        assert!(!holds(Condition::StringEqualsIgnoreCase { fact_name: name(), expected_value: text("eclair runner") }));
+        assert!(!holds(Condition::StringEqualsIgnoreCase { fact_name: name(), expected_value: text("éclair") }));
+        let pattern = RegexPattern::new(r"^\w+ R[a-z]+$").unwrap();
+        assert!(holds(Condition::StringMatches { fact_name: name(), pattern }));
+        let pattern = RegexPattern::new("^Runner").unwrap();
+        assert!(!holds(Condition::StringMatches { fact_name: name(), pattern }));
+        assert!(holds(Condition::StringIn { fact_name: name(), values: set(&["Pilot", "Éclair Runner"]) }));
+        assert!(!holds(Condition::StringIn { fact_name: name(), values: set(&["Pilot", "éclair runner"]) }));
+
+        // A fact of another type, or none at all, doesn't match
+        let holds = |condition: Condition| condition.evaluate(&list(&["Éclair Runner"]));
+        assert!(!holds(Condition::StringContains { fact_name: text("cargo"), expected_value: text("Runner") }));
+        assert!(!holds(Condition::StringStartsWith { fact_name: name(), prefix: String::new() }));
+    }
+
+    #[test]
+    fn list_conditions() {
+        let storage = list(&["fuel", "ore"]);
+        let holds = |condition: Condition| condition.evaluate(&storage);
+        let cargo = || "cargo".to_string();
+
+        assert!(holds(Condition::ListSize { fact_name: cargo(), comparison: Comparison::Equal, size: 2 }));
+        assert!(holds(Condition::ListSize { fact_name: cargo(), comparison: Comparison::Greater, size: 1 }));
+        assert!(!holds(Condition::ListSize { fact_name: cargo(), comparison: Comparison::Less, size: 2 }));
+        assert!(holds(Condition::ListSubsetOf { fact_name: cargo(), values: set(&["fuel", "ore", "water"]) }));
+        assert!(!holds(Condition::ListSubsetOf { fact_name: cargo(), values: set(&["fuel", "water"]) }));
+        assert!(holds(Condition::ListSupersetOf { fact_name: cargo(), values: set(&["ore"]) }));
+        assert!(!holds(Condition::ListSupersetOf { fact_name: cargo(), values: set(&["ore", "water"]) }));
+        assert!(holds(Condition::ListIntersects { fact_name: cargo(), values: set(&["water", "ore"]) }));
+        assert!(!holds(Condition::ListIntersects { fact_name: cargo(), values: set(&["water"]) }));
+        assert!(!holds(Condition::ListIntersects { fact_name: cargo(), values: StringHashSet::new() }));
+
+        // The empty list is a subset of anything and has nothing in common with it
+        let storage = list(&[]);
+        assert!(Condition::ListSubsetOf { fact_name: cargo(), values: set(&["fuel"]) }.evaluate(&storage));
+        assert!(!Condition::ListIntersects { fact_name: cargo(), values: set(&["fuel"]) }.evaluate(&storage));
+        assert!(!Condition::ListSize { fact_name: "name".to_string(), comparison: Comparison::Equal, size: 0 }
+            .evaluate(&string("")));
+    }
+}
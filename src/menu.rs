@@ -0,0 +1,254 @@
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, WindowMode};
+
+use crate::game_state::{GameState, StateScoped};
+use crate::{button_bundle, style_button};
+
+/// What a menu button does when it is activated
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuAction {
+    Play,
+    Settings,
+    Quit,
+    Resume,
+    Restart,
+    QuitToMenu,
+    ToggleFullscreen,
+    Back,
+}
+
+/// A button in the open menu, `index` is its position from the top
+#[derive(Component)]
+pub struct MenuButton {
+    index: usize,
+}
+
+/// The button with keyboard and gamepad focus, and the action taken by Escape or the
+/// gamepad's east button in the open menu. Reset whenever a menu is spawned.
+#[derive(Resource, Default)]
+pub struct MenuSelection {
+    selected: usize,
+    back: Option<MenuAction>,
+}
+
+#[derive(Event)]
+pub struct MenuActivated(pub MenuAction);
+
+// Spawn a full screen menu with a title and a column of buttons, despawned on leaving `state`
+fn spawn_menu(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    selection: &mut MenuSelection,
+    title: &str,
+    buttons: &[(&str, MenuAction)],
+    back: Option<MenuAction>,
+    state: GameState,
+) {
+    *selection = MenuSelection { selected: 0, back };
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(16.0),
+                    ..default()
+                },
+                background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.8)),
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            StateScoped::new(state),
+        ))
+        .with_children(|builder| {
+            builder.spawn(
+                TextBundle::from_section(
+                    title,
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 64.0,
+                        color: Color::WHITE,
+                    },
+                )
+                .with_style(Style {
+                    margin: UiRect::bottom(Val::Px(24.0)),
+                    ..default()
+                }),
+            );
+            for (index, (label, action)) in buttons.iter().enumerate() {
+                builder
+                    .spawn((button_bundle(Val::Px(300.0), Val::Px(65.0)), MenuButton { index }, *action))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle::from_section(
+                            *label,
+                            TextStyle {
+                                font: font.clone(),
+                                font_size: 32.0,
+                                color: Color::rgb(0.9, 0.9, 0.9),
+                            },
+                        ));
+                    });
+            }
+        });
+}
+
+pub fn spawn_main_menu(mut commands: Commands, asset_server: Res<AssetServer>, mut selection: ResMut<MenuSelection>) {
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        &mut selection,
+        "Rokkets",
+        &[("Play", MenuAction::Play), ("Settings", MenuAction::Settings), ("Quit", MenuAction::Quit)],
+        None,
+        GameState::MainMenu,
+    );
+}
+
+pub fn spawn_settings_menu(mut commands: Commands, asset_server: Res<AssetServer>, mut selection: ResMut<MenuSelection>) {
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        &mut selection,
+        "Settings",
+        &[("Fullscreen", MenuAction::ToggleFullscreen), ("Back", MenuAction::Back)],
+        Some(MenuAction::Back),
+        GameState::Settings,
+    );
+}
+
+pub fn spawn_pause_menu(mut commands: Commands, asset_server: Res<AssetServer>, mut selection: ResMut<MenuSelection>) {
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        &mut selection,
+        "Paused",
+        &[
+            ("Resume", MenuAction::Resume),
+            ("Restart", MenuAction::Restart),
+            ("Quit to menu", MenuAction::QuitToMenu),
+        ],
+        Some(MenuAction::Resume),
+        GameState::Paused,
+    );
+}
+
+pub fn spawn_game_over_menu(mut commands: Commands, asset_server: Res<AssetServer>, mut selection: ResMut<MenuSelection>) {
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        &mut selection,
+        "Game Over",
+        &[("Play again", MenuAction::Restart), ("Quit to menu", MenuAction::QuitToMenu)],
+        Some(MenuAction::QuitToMenu),
+        GameState::GameOver,
+    );
+}
+
+// Hovering a button moves the focus to it, clicking activates it
+pub fn menu_mouse(
+    query: Query<(&Interaction, &MenuButton, &MenuAction), Changed<Interaction>>,
+    mut selection: ResMut<MenuSelection>,
+    mut activated: EventWriter<MenuActivated>,
+) {
+    for (interaction, button, action) in query.iter() {
+        match interaction {
+            Interaction::Pressed => {
+                activated.send(MenuActivated(*action));
+            }
+            Interaction::Hovered => selection.selected = button.index,
+            Interaction::None => {}
+        }
+    }
+}
+
+// Move the focus with the arrow keys, W/S or the d-pad, activate with Enter, Space or the
+// gamepad's south button, and go back with Escape or the east button
+pub fn menu_navigation(
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    query: Query<(&MenuButton, &MenuAction)>,
+    mut selection: ResMut<MenuSelection>,
+    mut activated: EventWriter<MenuActivated>,
+) {
+    let pressed = |key_codes: &[KeyCode], button_type: GamepadButtonType| {
+        keys.any_just_pressed(key_codes.iter().copied())
+            || gamepads
+                .iter()
+                .any(|gamepad| gamepad_buttons.just_pressed(GamepadButton::new(gamepad, button_type)))
+    };
+
+    let count = query.iter().count();
+    if count == 0 {
+        return;
+    }
+    if pressed(&[KeyCode::ArrowUp, KeyCode::KeyW], GamepadButtonType::DPadUp) {
+        selection.selected = (selection.selected + count - 1) % count;
+    }
+    if pressed(&[KeyCode::ArrowDown, KeyCode::KeyS], GamepadButtonType::DPadDown) {
+        selection.selected = (selection.selected + 1) % count;
+    }
+    if pressed(&[KeyCode::Enter, KeyCode::Space], GamepadButtonType::South) {
+        if let Some((_, action)) = query.iter().find(|(button, _)| button.index == selection.selected) {
+            activated.send(MenuActivated(*action));
+        }
+    } else if pressed(&[KeyCode::Escape], GamepadButtonType::East) {
+        if let Some(back) = selection.back {
+            activated.send(MenuActivated(back));
+        }
+    }
+}
+
+// Show the focused button as hovered, whichever input moved the focus there
+pub fn style_menu_buttons(
+    selection: Res<MenuSelection>,
+    mut query: Query<(&Interaction, &MenuButton, &mut BackgroundColor, &mut BorderColor)>,
+) {
+    for (interaction, button, mut color, mut border_color) in query.iter_mut() {
+        let interaction = match interaction {
+            Interaction::Pressed => Interaction::Pressed,
+            _ if button.index == selection.selected => Interaction::Hovered,
+            _ => Interaction::None,
+        };
+        style_button(interaction, &mut color, &mut border_color);
+    }
+}
+
+pub fn menu_actions(
+    mut activated: EventReader<MenuActivated>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    for MenuActivated(action) in activated.read() {
+        match action {
+            MenuAction::Play | MenuAction::Restart => next_state.set(GameState::Loading),
+            MenuAction::Settings => next_state.set(GameState::Settings),
+            MenuAction::Quit => {
+                exit.send(AppExit);
+            }
+            MenuAction::Resume => next_state.set(GameState::Playing),
+            MenuAction::QuitToMenu => next_state.set(GameState::MainMenu),
+            MenuAction::ToggleFullscreen => {
+                for mut window in windows.iter_mut() {
+                    window.mode = match window.mode {
+                        WindowMode::Windowed => WindowMode::BorderlessFullscreen,
+                        _ => WindowMode::Windowed,
+                    };
+                }
+            }
+            MenuAction::Back => {
+                if *state.get() == GameState::Settings {
+                    next_state.set(GameState::MainMenu);
+                }
+            }
+        }
+    }
+}
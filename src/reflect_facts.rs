@@ -0,0 +1,109 @@
+use std::marker::PhantomData;
+
+use bevy::ecs::query::QueryFilter;
+use bevy::prelude::*;
+use bevy::reflect::ParsedPath;
+
+use crate::entity_facts::FactStore;
+use crate::{CoolFactStore, Fact, FloatValue};
+
+/// Field paths of a reflected component that are copied into facts whenever the component
+/// changes, e.g. `translation.y` of `Transform` into `ship.altitude`.
+#[derive(Resource)]
+pub struct ReflectFactBridge<C, F = ()> {
+    mappings: Vec<(ParsedPath, String)>,
+    marker: PhantomData<fn() -> (C, F)>,
+}
+
+impl<C, F> ReflectFactBridge<C, F> {
+    // Constructor for ReflectFactBridge. Panics if a path can't be parsed, since the
+    // mappings are written by hand at startup.
+    pub fn new(mappings: &[(&str, &str)]) -> Self {
+        ReflectFactBridge {
+            mappings: mappings
+                .iter()
+                .map(|(path, key)| {
+                    let parsed = ParsedPath::parse(path)
+                        .unwrap_or_else(|error| panic!("Invalid field path {}: {}", path, error));
+                    (parsed, key.to_string())
+                })
+                .collect(),
+            marker: PhantomData,
+        }
+    }
+}
+
+// Turn a reflected scalar into a fact, or `None` if it isn't a type facts can hold
+pub fn reflect_to_fact(key: &str, value: &dyn Reflect) -> Option<Fact> {
+    let key = key.to_string();
+    let any = value.as_any();
+    if let Some(value) = any.downcast_ref::<f32>() {
+        Some(Fact::Float(key, FloatValue(*value)))
+    } else if let Some(value) = any.downcast_ref::<f64>() {
+        Some(Fact::Float(key, FloatValue(*value as f32)))
+    } else if let Some(value) = any.downcast_ref::<i32>() {
+        Some(Fact::Int(key, *value))
+    } else if let Some(value) = any.downcast_ref::<u32>() {
+        Some(Fact::Int(key, *value as i32))
+    } else if let Some(value) = any.downcast_ref::<usize>() {
+        Some(Fact::Int(key, *value as i32))
+    } else if let Some(value) = any.downcast_ref::<bool>() {
+        Some(Fact::Bool(key, *value))
+    } else {
+        any.downcast_ref::<String>()
+            .map(|value| Fact::String(key, value.clone()))
+    }
+}
+
+type BridgedComponentQuery<'w, 's, C, F> =
+    Query<'w, 's, (&'static C, Option<&'static mut FactStore>), (Changed<C>, F)>;
+
+// Copy the mapped fields of changed components into the entity's own `FactStore` if it
+// has one, otherwise into the global store
+pub fn bridge_component_facts<C: Component + Reflect, F: QueryFilter + 'static>(
+    bridge: Res<ReflectFactBridge<C, F>>,
+    mut query: BridgedComponentQuery<C, F>,
+    mut storage: ResMut<CoolFactStore>,
+) {
+    for (component, mut entity_store) in query.iter_mut() {
+        let store: &mut CoolFactStore = match entity_store.as_mut() {
+            Some(entity_store) => entity_store,
+            None => &mut storage,
+        };
+        for (path, key) in &bridge.mappings {
+            match component.reflect_path(path) {
+                Ok(value) => match reflect_to_fact(key, value) {
+                    Some(fact) => store.store_fact(fact, None),
+                    None => warn!("Field {} can't be stored as fact {}", path, key),
+                },
+                Err(error) => warn!("Field {} not found for fact {}: {}", path, key, error),
+            }
+        }
+    }
+}
+
+pub trait ReflectFactsAppExt {
+    // Copy the given `(field path, fact key)` pairs of every `C` into facts when it changes
+    fn bridge_component_facts<C: Component + Reflect>(&mut self, mappings: &[(&str, &str)]) -> &mut Self;
+
+    // Like `bridge_component_facts`, but only for entities matching the query filter `F`,
+    // e.g. `With<ShipStats>` to leave the camera's `Transform` alone
+    fn bridge_filtered_component_facts<C: Component + Reflect, F: QueryFilter + 'static>(
+        &mut self,
+        mappings: &[(&str, &str)],
+    ) -> &mut Self;
+}
+
+impl ReflectFactsAppExt for App {
+    fn bridge_component_facts<C: Component + Reflect>(&mut self, mappings: &[(&str, &str)]) -> &mut Self {
+        self.bridge_filtered_component_facts::<C, ()>(mappings)
+    }
+
+    fn bridge_filtered_component_facts<C: Component + Reflect, F: QueryFilter + 'static>(
+        &mut self,
+        mappings: &[(&str, &str)],
+    ) -> &mut Self {
+        self.insert_resource(ReflectFactBridge::<C, F>::new(mappings))
+            .add_systems(Update, bridge_component_facts::<C, F>)
+    }
+}
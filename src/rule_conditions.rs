@@ -0,0 +1,87 @@
+use bevy::prelude::*;
+
+use crate::{CoolFactStore, IntoFact, RuleEngine, RuleUpdated};
+
+// Run condition that is true while the global rule is active, e.g.
+// `.run_if(rule_active("boss_phase_2"))`
+pub fn rule_active(rule: &str) -> impl FnMut(Res<RuleEngine>) -> bool + Clone {
+    let rule = rule.to_string();
+    move |rules: Res<RuleEngine>| rules.is_active(&rule)
+}
+
+// Run condition that is true while the global fact holds the value, e.g.
+// `.run_if(fact_equals("paused", true))`
+pub fn fact_equals(key: &str, value: impl IntoFact) -> impl FnMut(Res<CoolFactStore>) -> bool + Clone {
+    let expected = value.into_fact(key.to_string());
+    move |storage: Res<CoolFactStore>| storage.facts.get(expected.key()) == Some(&expected)
+}
+
+// Run condition that is true while the global fact exists
+pub fn fact_exists(key: &str) -> impl FnMut(Res<CoolFactStore>) -> bool + Clone {
+    let key = key.to_string();
+    move |storage: Res<CoolFactStore>| storage.facts.contains_key(&key)
+}
+
+// Run condition that is true on frames where the global rule became active, e.g.
+// `.run_if(rule_activated("boss_phase_2"))`
+pub fn rule_activated(rule: &str) -> impl FnMut(EventReader<RuleUpdated>) -> bool + Clone {
+    rule_changed_to(rule, true)
+}
+
+// Run condition that is true on frames where the global rule stopped being active
+pub fn rule_deactivated(rule: &str) -> impl FnMut(EventReader<RuleUpdated>) -> bool + Clone {
+    rule_changed_to(rule, false)
+}
+
+fn rule_changed_to(rule: &str, state: bool) -> impl FnMut(EventReader<RuleUpdated>) -> bool + Clone {
+    let rule = rule.to_string();
+    move |mut rule_updated: EventReader<RuleUpdated>| {
+        // Every event is read, so the same change never counts twice
+        rule_updated
+            .read()
+            .filter(|event| event.entity.is_none() && event.rule == rule && event.new_state == state)
+            .count() > 0
+    }
+}
+
+pub trait RuleListenerAppExt {
+    // Run `systems` in `Update` on the frames where the global rule becomes active. The
+    // `RuleUpdated` event is still there to read for the facts that triggered it.
+    fn on_rule_activated<M>(&mut self, rule: &str, systems: impl IntoSystemConfigs<M>) -> &mut Self;
+    // Run `systems` in `Update` on the frames where the global rule stops being active
+    fn on_rule_deactivated<M>(&mut self, rule: &str, systems: impl IntoSystemConfigs<M>) -> &mut Self;
+}
+
+impl RuleListenerAppExt for App {
+    fn on_rule_activated<M>(&mut self, rule: &str, systems: impl IntoSystemConfigs<M>) -> &mut Self {
+        self.add_systems(Update, systems.run_if(rule_activated(rule)))
+    }
+
+    fn on_rule_deactivated<M>(&mut self, rule: &str, systems: impl IntoSystemConfigs<M>) -> &mut Self {
+        self.add_systems(Update, systems.run_if(rule_deactivated(rule)))
+    }
+}
+
+pub trait RuleStatesAppExt {
+    // Move to `state` whenever the global rule becomes active. The state type has to be
+    // registered with `init_state` or `insert_state`.
+    fn transition_on_rule<S: States>(&mut self, rule: &str, state: S) -> &mut Self;
+}
+
+impl RuleStatesAppExt for App {
+    fn transition_on_rule<S: States>(&mut self, rule: &str, state: S) -> &mut Self {
+        let rule = rule.to_string();
+        self.add_systems(
+            Update,
+            move |mut rule_updated: EventReader<RuleUpdated>, mut next_state: ResMut<NextState<S>>| {
+                let activated = rule_updated
+                    .read()
+                    .filter(|event| event.entity.is_none() && event.rule == rule && event.new_state)
+                    .count() > 0;
+                if activated {
+                    next_state.set(state.clone());
+                }
+            },
+        )
+    }
+}
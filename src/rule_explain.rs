@@ -0,0 +1,286 @@
+use std::time::Duration;
+
+use crate::fact_expr::FactExpr;
+use crate::fact_namespace::{child_namespaces, ScopedFacts};
+use crate::rule_timers::ConditionTimer;
+use crate::{Condition, FactKind, FactSource, Rule, RuleEngine, StringHashSet};
+
+/// What was found when looking up the fact a condition depends on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FactCheck {
+    // The fact exists with the right type, shown as its value
+    Value(String),
+    Missing(String),
+    WrongType { key: String, expected: FactKind, found: FactKind },
+    // A fact used in arithmetic that isn't an int or float
+    NotNumeric { key: String, found: FactKind },
+    // A time-windowed condition on a fact whose history isn't tracked
+    NoHistory(String),
+    // Nothing to look up, e.g. for a rule or a namespace condition
+    None,
+}
+
+/// Why a rule or condition is true or false, with one child per condition it is made of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub description: String,
+    pub passed: bool,
+    pub check: FactCheck,
+    pub children: Vec<Explanation>,
+}
+
+impl Explanation {
+    // The tree as indented lines, e.g.
+    // `false  game_over_rule` / `  false  player1.lives < 1 (player1.lives = 3)`
+    pub fn render(&self) -> String {
+        let mut lines = Vec::new();
+        self.render_into(0, &mut lines);
+        lines.join("\n")
+    }
+
+    fn render_into(&self, depth: usize, lines: &mut Vec<String>) {
+        let check = match &self.check {
+            FactCheck::Value(value) => format!(" ({})", value),
+            FactCheck::Missing(key) => format!(" (fact {} is missing)", key),
+            FactCheck::WrongType { key, expected, found } => {
+                format!(" (fact {} is {:?}, expected {:?})", key, found, expected)
+            }
+            FactCheck::NotNumeric { key, found } => format!(" (fact {} is {:?}, not a number)", key, found),
+            FactCheck::NoHistory(key) => format!(" (history of {} isn't tracked)", key),
+            FactCheck::None => String::new(),
+        };
+        lines.push(format!("{}{}  {}{}", "  ".repeat(depth), self.passed, self.description, check));
+        for child in &self.children {
+            child.render_into(depth + 1, lines);
+        }
+    }
+}
+
+// A set of values written out in order, e.g. `{"blue", "red"}`
+fn describe_set(values: &StringHashSet) -> String {
+    let values: Vec<String> = values.sorted().into_iter().map(|value| format!("{:?}", value)).collect();
+    format!("{{{}}}", values.join(", "))
+}
+
+fn check_fact(facts: &dyn FactSource, key: &str, expected: FactKind) -> FactCheck {
+    match facts.fact(key) {
+        None => FactCheck::Missing(key.to_string()),
+        Some(fact) if fact.kind() != expected => FactCheck::WrongType {
+            key: key.to_string(),
+            expected,
+            found: fact.kind(),
+        },
+        Some(fact) => FactCheck::Value(format!("{} = {}", key, fact.value_string())),
+    }
+}
+
+// The values of every fact in an expression, or the first one that is missing or not a number
+fn check_numeric_facts(facts: &dyn FactSource, expr: &[&FactExpr]) -> FactCheck {
+    let mut values = Vec::new();
+    for key in expr.iter().flat_map(|expr| expr.fact_names()) {
+        match facts.fact(key) {
+            None => return FactCheck::Missing(key.to_string()),
+            Some(fact) if fact.as_f32().is_none() => {
+                return FactCheck::NotNumeric { key: key.to_string(), found: fact.kind() }
+            }
+            Some(fact) => values.push(format!("{} = {}", key, fact.value_string())),
+        }
+    }
+    FactCheck::Value(values.join(", "))
+}
+
+fn check_history(facts: &dyn FactSource, key: &str, within: Option<Duration>) -> FactCheck {
+    match facts.timeline(key) {
+        None => FactCheck::NoHistory(key.to_string()),
+        Some(timeline) => match (within, timeline.time_since_last_change(facts.now())) {
+            (Some(within), _) => FactCheck::Value(format!(
+                "{} changed {} times within {:?}",
+                key,
+                timeline.changes_within(within, facts.now()),
+                within
+            )),
+            (None, Some(since)) => FactCheck::Value(format!("{} last changed {:?} ago", key, since)),
+            (None, None) => FactCheck::Value(format!("{} never changed", key)),
+        },
+    }
+}
+
+impl Condition {
+    // The condition written out, e.g. `player1.lives < 1`
+    pub fn describe(&self) -> String {
+        match self {
+            Condition::IntEquals { fact_name, expected_value } => format!("{} == {}", fact_name, expected_value),
+            Condition::IntMoreThan { fact_name, expected_value } => format!("{} > {}", fact_name, expected_value),
+            Condition::IntLessThan { fact_name, expected_value } => format!("{} < {}", fact_name, expected_value),
+            Condition::StringEquals { fact_name, expected_value } => format!("{} == {:?}", fact_name, expected_value),
+            Condition::BoolEquals { fact_name, expected_value } => format!("{} == {}", fact_name, expected_value),
+            Condition::ListContains { fact_name, expected_value } => format!("{} has {:?}", fact_name, expected_value),
+            Condition::StringStartsWith { fact_name, prefix } => format!("{} starts with {:?}", fact_name, prefix),
+            Condition::StringEndsWith { fact_name, suffix } => format!("{} ends with {:?}", fact_name, suffix),
+            Condition::StringContains { fact_name, expected_value } => {
+                format!("{} contains {:?}", fact_name, expected_value)
+            }
+            Condition::StringEqualsIgnoreCase { fact_name, expected_value } => {
+                format!("{} == {:?} ignoring case", fact_name, expected_value)
+            }
+            Condition::StringMatches { fact_name, pattern } => format!("{} matches /{}/", fact_name, pattern.0.as_str()),
+            Condition::StringIn { fact_name, values } => format!("{} in {}", fact_name, describe_set(values)),
+            Condition::ListSize { fact_name, comparison, size } => {
+                format!("size of {} {} {}", fact_name, comparison.symbol(), size)
+            }
+            Condition::ListSubsetOf { fact_name, values } => format!("{} within {}", fact_name, describe_set(values)),
+            Condition::ListSupersetOf { fact_name, values } => {
+                format!("{} has all of {}", fact_name, describe_set(values))
+            }
+            Condition::ListIntersects { fact_name, values } => {
+                format!("{} has any of {}", fact_name, describe_set(values))
+            }
+            Condition::FloatMoreThan { fact_name, expected_value } => format!("{} > {}", fact_name, expected_value.0),
+            Condition::FloatLessThan { fact_name, expected_value } => format!("{} < {}", fact_name, expected_value.0),
+            Condition::ChangedWithin { fact_name, within } => format!("{} changed within {:?}", fact_name, within),
+            Condition::ChangeCountAtLeast { fact_name, count, within } => {
+                format!("{} changed at least {} times within {:?}", fact_name, count, within)
+            }
+            Condition::UnchangedFor { fact_name, duration } => format!("{} unchanged for {:?}", fact_name, duration),
+            Condition::HasFactsUnder { namespace } => format!("has facts under {}", namespace),
+            Condition::AnyUnder { namespace, .. } => format!("any under {}", namespace),
+            Condition::AllUnder { namespace, .. } => format!("all under {}", namespace),
+            Condition::Compare { left, comparison, right } => {
+                format!("{} {} {}", left, comparison.symbol(), right)
+            }
+            Condition::HeldFor { condition, duration } => format!("({}) held for {:?}", condition.describe(), duration),
+            Condition::NotWithin { condition, duration } => {
+                format!("({}) not within {:?}", condition.describe(), duration)
+            }
+        }
+    }
+
+    // Evaluate the condition and say why it came out the way it did
+    pub fn explain(&self, facts: &dyn FactSource) -> Explanation {
+        let check = match self {
+            Condition::IntEquals { fact_name, .. }
+            | Condition::IntMoreThan { fact_name, .. }
+            | Condition::IntLessThan { fact_name, .. } => check_fact(facts, fact_name, FactKind::Int),
+            Condition::StringEquals { fact_name, .. }
+            | Condition::StringStartsWith { fact_name, .. }
+            | Condition::StringEndsWith { fact_name, .. }
+            | Condition::StringContains { fact_name, .. }
+            | Condition::StringEqualsIgnoreCase { fact_name, .. }
+            | Condition::StringMatches { fact_name, .. }
+            | Condition::StringIn { fact_name, .. } => check_fact(facts, fact_name, FactKind::String),
+            Condition::BoolEquals { fact_name, .. } => check_fact(facts, fact_name, FactKind::Bool),
+            Condition::ListContains { fact_name, .. }
+            | Condition::ListSize { fact_name, .. }
+            | Condition::ListSubsetOf { fact_name, .. }
+            | Condition::ListSupersetOf { fact_name, .. }
+            | Condition::ListIntersects { fact_name, .. } => check_fact(facts, fact_name, FactKind::StringList),
+            Condition::FloatMoreThan { fact_name, .. } | Condition::FloatLessThan { fact_name, .. } => {
+                check_fact(facts, fact_name, FactKind::Float)
+            }
+            Condition::ChangedWithin { fact_name, within }
+            | Condition::ChangeCountAtLeast { fact_name, within, .. } => check_history(facts, fact_name, Some(*within)),
+            Condition::UnchangedFor { fact_name, .. } => check_history(facts, fact_name, None),
+            Condition::Compare { left, right, .. } => check_numeric_facts(facts, &[left, right]),
+            Condition::HasFactsUnder { namespace } => {
+                FactCheck::Value(format!("{} facts under {}", facts.keys_under(namespace).len(), namespace))
+            }
+            Condition::AnyUnder { namespace, condition } | Condition::AllUnder { namespace, condition } => {
+                let children: Vec<Explanation> = child_namespaces(facts, namespace)
+                    .into_iter()
+                    .map(|child| {
+                        let mut explanation = condition.explain(&ScopedFacts::new(facts, child.clone()));
+                        explanation.description = format!("{}: {}", child, explanation.description);
+                        explanation
+                    })
+                    .collect();
+                let passed = match self {
+                    Condition::AnyUnder { .. } => children.iter().any(|child| child.passed),
+                    _ => !children.is_empty() && children.iter().all(|child| child.passed),
+                };
+                return Explanation {
+                    description: self.describe(),
+                    passed,
+                    check: FactCheck::None,
+                    children,
+                };
+            }
+            Condition::HeldFor { condition, .. } | Condition::NotWithin { condition, .. } => {
+                // Only the rule engine knows how long the condition has held, see `RuleEngine::explain`
+                return Explanation {
+                    description: self.describe(),
+                    passed: false,
+                    check: FactCheck::Value("no timer".to_string()),
+                    children: vec![condition.explain(facts)],
+                };
+            }
+        };
+        Explanation {
+            description: self.describe(),
+            passed: self.evaluate(facts),
+            check,
+            children: Vec::new(),
+        }
+    }
+}
+
+impl Rule {
+    pub fn explain(&self, facts: &dyn FactSource) -> Explanation {
+        let children: Vec<Explanation> = self.conditions.iter().map(|condition| condition.explain(facts)).collect();
+        Explanation {
+            description: self.name.clone(),
+            passed: children.iter().all(|child| child.passed),
+            check: FactCheck::None,
+            children,
+        }
+    }
+}
+
+// What the timer of a duration condition says, e.g. `held for 1.2s of 5s`
+fn check_timer(condition: &Condition, timer: &ConditionTimer, now: Duration) -> (bool, FactCheck) {
+    match condition {
+        Condition::HeldFor { duration, .. } => match timer.held_for(now) {
+            Some(held) => (held >= *duration, FactCheck::Value(format!("held for {:?} of {:?}", held, duration))),
+            None => (false, FactCheck::Value("not holding".to_string())),
+        },
+        Condition::NotWithin { duration, .. } => {
+            let since = timer.since_last_true(now);
+            (since >= *duration && since > Duration::ZERO, FactCheck::Value(format!("last held {:?} ago", since)))
+        }
+        _ => (false, FactCheck::None),
+    }
+}
+
+impl RuleEngine {
+    // Explain a rule against the given facts, or `None` if there is no such rule. A disabled
+    // rule is explained as false whatever its conditions say. Duration conditions at the top
+    // of a global rule are explained with their timers.
+    pub fn explain(&self, rule: &str, facts: &dyn FactSource) -> Option<Explanation> {
+        let mut explanation = self.rules.get(rule)?.explain(facts);
+        for (index, condition) in self.rules[rule].conditions.iter().enumerate() {
+            if let Some(timer) = self.timer(&format!("{}:{}", rule, index)) {
+                let (passed, check) = check_timer(condition, timer, facts.now());
+                explanation.children[index].passed = passed;
+                explanation.children[index].check = check;
+            }
+        }
+        explanation.passed = explanation.children.iter().all(|child| child.passed);
+        if !self.is_enabled(rule) {
+            explanation.passed = false;
+            explanation.description = format!("{} (disabled)", explanation.description);
+        }
+        Some(explanation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_and_string_contains_read_differently() {
+        let list = Condition::ListContains { fact_name: "cargo".to_string(), expected_value: "ore".to_string() };
+        let string = Condition::StringContains { fact_name: "cargo".to_string(), expected_value: "ore".to_string() };
+        assert_eq!(list.describe(), r#"cargo has "ore""#);
+        assert_eq!(string.describe(), r#"cargo contains "ore""#);
+    }
+}
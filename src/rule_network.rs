@@ -0,0 +1,572 @@
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+use bevy::utils::hashbrown::{HashMap, HashSet};
+
+use crate::fact_expr::{Comparison, FactExpr};
+use crate::{Condition, CoolFactStore, FactSource, Rule, RuleEngine};
+
+// One distinct condition, shared by every rule that uses it
+struct ConditionNode {
+    condition: Condition,
+    holds: bool,
+    rules: Vec<usize>,
+}
+
+// A rule as the distinct condition nodes it needs, with how many of them hold right now
+struct RuleNode {
+    name: String,
+    conditions: Vec<usize>,
+    satisfied: usize,
+}
+
+impl RuleNode {
+    fn holds(&self) -> bool {
+        self.satisfied == self.conditions.len()
+    }
+}
+
+/// The global rules compiled into a matching network in the style of Rete. Identical
+/// conditions become one node, evaluated once for every rule that uses it, and each rule
+/// keeps how many of its conditions hold. A fact change only re-evaluates the nodes that
+/// read the fact and passes on the difference. Rules with conditions that depend on time
+/// passing aren't compiled and are evaluated the usual way.
+pub struct RuleNetwork {
+    // The `RuleEngine` revision the network was compiled from
+    revision: u64,
+    nodes: Vec<ConditionNode>,
+    // Nodes by the fact or namespace they read
+    readers: HashMap<String, Vec<usize>>,
+    rules: Vec<RuleNode>,
+    rule_index: HashMap<String, usize>,
+    uncompiled: Vec<String>,
+}
+
+impl RuleNetwork {
+    // Compile the rules, in name order so the network comes out the same every time. Call
+    // `evaluate_all` before the first `update`.
+    pub fn compile<'a>(rules: impl IntoIterator<Item = &'a Rule>, revision: u64) -> Self {
+        let mut rules: Vec<&Rule> = rules.into_iter().collect();
+        rules.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut network = RuleNetwork {
+            revision,
+            nodes: Vec::new(),
+            readers: HashMap::new(),
+            rules: Vec::new(),
+            rule_index: HashMap::new(),
+            uncompiled: Vec::new(),
+        };
+        let mut node_index: HashMap<&Condition, usize> = HashMap::new();
+        for rule in rules {
+            if rule.conditions.iter().any(Condition::depends_on_time) {
+                network.uncompiled.push(rule.name.clone());
+                continue;
+            }
+            let rule_id = network.rules.len();
+            let mut conditions = Vec::new();
+            for condition in &rule.conditions {
+                let id = *node_index.entry(condition).or_insert_with(|| {
+                    network.nodes.push(ConditionNode { condition: condition.clone(), holds: false, rules: Vec::new() });
+                    network.nodes.len() - 1
+                });
+                // A condition listed twice only has to hold once
+                if !conditions.contains(&id) {
+                    conditions.push(id);
+                    network.nodes[id].rules.push(rule_id);
+                }
+            }
+            network.rule_index.insert(rule.name.clone(), rule_id);
+            network.rules.push(RuleNode { name: rule.name.clone(), conditions, satisfied: 0 });
+        }
+        for (id, node) in network.nodes.iter().enumerate() {
+            for name in node.condition.fact_names() {
+                network.readers.entry(name.to_string()).or_default().push(id);
+            }
+        }
+        network
+    }
+
+    // Evaluate every node from scratch
+    pub fn evaluate_all(&mut self, facts: &dyn FactSource) {
+        for node in &mut self.nodes {
+            node.holds = node.condition.evaluate(facts);
+        }
+        for rule in &mut self.rules {
+            rule.satisfied = rule.conditions.iter().filter(|id| self.nodes[**id].holds).count();
+        }
+    }
+
+    // Re-evaluate the nodes that read one of the changed facts, and return the rules whose
+    // conditions went from all holding to not, or back
+    pub fn update(&mut self, changed: &HashSet<String>, facts: &dyn FactSource) -> Vec<String> {
+        // A node reading a namespace reads every key below it
+        let mut dirty: BTreeSet<usize> = BTreeSet::new();
+        for key in changed {
+            let prefixes = key.match_indices('.').map(|(i, _)| &key[..i]).chain([key.as_str()]);
+            for prefix in prefixes {
+                if let Some(ids) = self.readers.get(prefix) {
+                    dirty.extend(ids);
+                }
+            }
+        }
+
+        let mut flipped: BTreeSet<usize> = BTreeSet::new();
+        for id in dirty {
+            let node = &mut self.nodes[id];
+            let holds = node.condition.evaluate(facts);
+            if holds == node.holds {
+                continue;
+            }
+            node.holds = holds;
+            for rule_id in &node.rules {
+                let rule = &mut self.rules[*rule_id];
+                let before = rule.holds();
+                if holds {
+                    rule.satisfied += 1;
+                } else {
+                    rule.satisfied -= 1;
+                }
+                // Flipping back within the same update is no change at all
+                if before != rule.holds() && !flipped.insert(*rule_id) {
+                    flipped.remove(rule_id);
+                }
+            }
+        }
+        flipped.into_iter().map(|id| self.rules[id].name.clone()).collect()
+    }
+
+    // Whether all conditions of the rule hold, or `None` if it isn't compiled
+    pub fn holds(&self, rule: &str) -> Option<bool> {
+        self.rule_index.get(rule).map(|id| self.rules[*id].holds())
+    }
+
+    // Rules left out of the network because they depend on time passing
+    pub fn uncompiled(&self) -> &[String] {
+        &self.uncompiled
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+}
+
+impl Condition {
+    // Whether the condition can change just because time passes, or keeps timers
+    pub fn depends_on_time(&self) -> bool {
+        match self {
+            Condition::ChangedWithin { .. }
+            | Condition::ChangeCountAtLeast { .. }
+            | Condition::UnchangedFor { .. }
+            | Condition::HeldFor { .. }
+            | Condition::NotWithin { .. } => true,
+            Condition::AnyUnder { condition, .. } | Condition::AllUnder { condition, .. } => {
+                condition.depends_on_time()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl RuleEngine {
+    // Match the global rules with a `RuleNetwork` instead of checking every rule that reads
+    // a changed fact. Worth it for large rule sets that share many conditions.
+    pub fn set_use_network(&mut self, enabled: bool) {
+        self.use_network = enabled;
+        self.network = None;
+        self.revision += 1;
+    }
+
+    pub fn network(&self) -> Option<&RuleNetwork> {
+        self.network.as_ref()
+    }
+}
+
+// A small xorshift generator, so the benchmark builds the same rules on every run
+struct BenchRandom(u64);
+
+impl BenchRandom {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+// Build `rule_count` rules of three conditions each, drawn from a shared pool, then change
+// `change_count` facts one at a time. Each change is matched with the naive
+// `evaluate_rules`, with `evaluate_rules_affected_by`, and with the network, and their rule
+// states are compared after every change. Returns the exit code, 1 if they ever differ.
+pub fn benchmark(rule_count: usize, change_count: usize) -> i32 {
+    const FACTS: usize = 500;
+    const SHARED_CONDITIONS: usize = 2000;
+    let mut random = BenchRandom(0x2545_f491_4f6c_dd1d);
+    let key = |i: usize| format!("bench.fact{}", i);
+
+    let pool: Vec<Condition> = (0..SHARED_CONDITIONS)
+        .map(|_| {
+            let fact_name = key(random.below(FACTS));
+            let expected_value = random.below(100) as i32;
+            match random.below(3) {
+                0 => Condition::IntMoreThan { fact_name, expected_value },
+                1 => Condition::IntLessThan { fact_name, expected_value },
+                _ => Condition::Compare {
+                    left: FactExpr::Fact(fact_name),
+                    comparison: Comparison::GreaterOrEqual,
+                    right: FactExpr::Fact(key(random.below(FACTS))),
+                },
+            }
+        })
+        .collect();
+
+    let mut storage = CoolFactStore::new();
+    for i in 0..FACTS {
+        storage.store_int(key(i), random.below(100) as i32);
+    }
+    let mut engines = [RuleEngine::new(), RuleEngine::new(), RuleEngine::new()];
+    for i in 0..rule_count {
+        let conditions = (0..3).map(|_| pool[random.below(SHARED_CONDITIONS)].clone()).collect();
+        let rule = Rule::new(format!("bench_rule{}", i), conditions);
+        for engine in &mut engines {
+            engine.add_rule(rule.clone());
+        }
+    }
+    engines[2].set_use_network(true);
+    for engine in &mut engines {
+        engine.evaluate_rules(&storage);
+    }
+
+    let mut timings = [Duration::ZERO; 3];
+    let mut mismatches = 0;
+    for _ in 0..change_count {
+        let changed_key = key(random.below(FACTS));
+        storage.store_int(changed_key.clone(), random.below(100) as i32);
+        let changed: HashSet<String> = HashSet::from([changed_key]);
+        for (i, engine) in engines.iter_mut().enumerate() {
+            let started = Instant::now();
+            if i == 0 {
+                engine.evaluate_rules(&storage);
+            } else {
+                engine.evaluate_rules_affected_by(&changed, &storage);
+            }
+            timings[i] += started.elapsed();
+        }
+        if engines[1].rule_states != engines[0].rule_states || engines[2].rule_states != engines[0].rule_states {
+            mismatches += 1;
+        }
+    }
+
+    let network = engines[2].network().expect("the network engine compiles a network");
+    if cfg!(debug_assertions) {
+        println!("debug build, timings aren't representative, run with --release");
+    }
+    println!(
+        "{} rules, {} of them compiled into {} shared condition nodes, {} changes",
+        rule_count,
+        network.rule_count(),
+        network.node_count(),
+        change_count
+    );
+    let labels = ["naive evaluate_rules", "evaluate_rules_affected_by", "rule network"];
+    for (label, timing) in labels.iter().zip(timings) {
+        let per_change = timing / change_count.max(1) as u32;
+        println!("{:<28} {:>10.2?} total, {:>10.2?} per change", label, timing, per_change);
+    }
+    let active = engines[0].rule_states.values().filter(|active| **active).count();
+    if mismatches == 0 {
+        println!("identical results after every change, {} rules active at the end", active);
+        0
+    } else {
+        println!("results differed after {} of {} changes", mismatches, change_count);
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule_policy::RulePolicy;
+    use crate::Fact;
+
+    // The same rules matched by an engine that evaluates every rule on every step and one that
+    // uses the network and only hears about the changed facts, as `rule_evaluator` does
+    struct SideBySide {
+        naive: RuleEngine,
+        network: RuleEngine,
+        storage: CoolFactStore,
+        changed: HashSet<String>,
+    }
+
+    impl SideBySide {
+        fn new(rules: Vec<Rule>) -> Self {
+            let mut naive = RuleEngine::new();
+            let mut network = RuleEngine::new();
+            for rule in rules {
+                naive.add_rule(rule.clone());
+                network.add_rule(rule);
+            }
+            network.set_use_network(true);
+            let storage = CoolFactStore::new();
+            let mut side_by_side = SideBySide { naive, network, storage, changed: HashSet::new() };
+            side_by_side.step();
+            side_by_side
+        }
+
+        fn both(&mut self, change: impl Fn(&mut RuleEngine)) {
+            change(&mut self.naive);
+            change(&mut self.network);
+        }
+
+        fn store(&mut self, fact: Fact) {
+            self.changed.insert(fact.key().to_string());
+            self.storage.store_fact(fact, None);
+        }
+
+        fn remove(&mut self, key: &str) {
+            self.changed.insert(key.to_string());
+            self.storage.remove_fact(key);
+        }
+
+        fn advance_to(&mut self, secs: u64) {
+            self.storage.advance_clock(self.storage.frame + 1, Duration::from_secs(secs));
+        }
+
+        // Match the changes so far with both engines and check they agree
+        fn step(&mut self) {
+            self.naive.evaluate_rules(&self.storage);
+            self.network.evaluate_rules_affected_by(&self.changed, &self.storage);
+            self.changed.clear();
+            assert_eq!(self.naive.rule_states, self.network.rule_states);
+            let network = self.network.network().expect("the network engine compiles a network");
+            assert_eq!(network.revision(), self.network.revision());
+        }
+
+        fn active(&self) -> Vec<&str> {
+            let mut active: Vec<&str> =
+                self.naive.rule_states.iter().filter(|(_, active)| **active).map(|(name, _)| name.as_str()).collect();
+            active.sort();
+            active
+        }
+    }
+
+    fn int(key: &str, value: i32) -> Fact {
+        Fact::Int(key.to_string(), value)
+    }
+
+    fn more_than(fact_name: &str, expected_value: i32) -> Condition {
+        Condition::IntMoreThan { fact_name: fact_name.to_string(), expected_value }
+    }
+
+    #[test]
+    fn namespace_conditions() {
+        let rules = vec![
+            Rule::new(
+                "any_low".to_string(),
+                vec![Condition::AnyUnder {
+                    namespace: "pods".to_string(),
+                    condition: Box::new(Condition::IntLessThan { fact_name: "fuel".to_string(), expected_value: 10 }),
+                }],
+            ),
+            Rule::new(
+                "all_landed".to_string(),
+                vec![
+                    Condition::HasFactsUnder { namespace: "pods".to_string() },
+                    Condition::AllUnder {
+                        namespace: "pods".to_string(),
+                        condition: Box::new(Condition::BoolEquals {
+                            fact_name: "landed".to_string(),
+                            expected_value: true,
+                        }),
+                    },
+                ],
+            ),
+            Rule::new("has_pods".to_string(), vec![Condition::HasFactsUnder { namespace: "pods".to_string() }]),
+        ];
+        let mut rules = SideBySide::new(rules);
+        assert!(rules.active().is_empty());
+
+        rules.store(int("pods.one.fuel", 50));
+        rules.store(Fact::Bool("pods.one.landed".to_string(), true));
+        rules.step();
+        assert_eq!(rules.active(), ["all_landed", "has_pods"]);
+
+        rules.store(int("pods.two.fuel", 5));
+        rules.store(Fact::Bool("pods.two.landed".to_string(), false));
+        rules.step();
+        assert_eq!(rules.active(), ["any_low", "has_pods"]);
+
+        rules.store(Fact::Bool("pods.two.landed".to_string(), true));
+        rules.store(int("pods.two.fuel", 80));
+        rules.step();
+        assert_eq!(rules.active(), ["all_landed", "has_pods"]);
+
+        rules.store(int("pods.one.fuel", 1));
+        rules.step();
+        rules.remove("pods.one.fuel");
+        rules.remove("pods.one.landed");
+        rules.step();
+        assert_eq!(rules.active(), ["all_landed", "has_pods"]);
+
+        rules.remove("pods.two.fuel");
+        rules.remove("pods.two.landed");
+        rules.step();
+        assert!(rules.active().is_empty());
+    }
+
+    #[test]
+    fn fact_removal() {
+        let compare = Condition::Compare {
+            left: FactExpr::Fact("fuel".to_string()),
+            comparison: Comparison::GreaterOrEqual,
+            right: FactExpr::Fact("reserve".to_string()),
+        };
+        let mut rules = SideBySide::new(vec![
+            Rule::new("fueled".to_string(), vec![more_than("fuel", 0)]),
+            Rule::new("above_reserve".to_string(), vec![compare]),
+        ]);
+        rules.store(int("fuel", 10));
+        rules.store(int("reserve", 5));
+        rules.step();
+        assert_eq!(rules.active(), ["above_reserve", "fueled"]);
+
+        rules.remove("reserve");
+        rules.step();
+        assert_eq!(rules.active(), ["fueled"]);
+        rules.remove("fuel");
+        rules.step();
+        assert!(rules.active().is_empty());
+        rules.store(int("fuel", 3));
+        rules.step();
+        assert_eq!(rules.active(), ["fueled"]);
+    }
+
+    #[test]
+    fn disabled_rules_and_groups() {
+        let mut rules = SideBySide::new(vec![
+            Rule::new("fueled".to_string(), vec![more_than("fuel", 0)]),
+            Rule::new("level_fueled".to_string(), vec![more_than("fuel", 0)]).in_group("level1"),
+        ]);
+        rules.store(int("fuel", 10));
+        rules.step();
+        assert_eq!(rules.active(), ["fueled", "level_fueled"]);
+        let revision = rules.network.revision();
+
+        rules.both(|engine| {
+            engine.set_enabled("fueled", false);
+        });
+        rules.step();
+        assert_eq!(rules.active(), ["level_fueled"]);
+        rules.both(|engine| {
+            engine.set_group_enabled("level1", false);
+        });
+        rules.store(int("fuel", 20));
+        rules.step();
+        assert!(rules.active().is_empty());
+
+        rules.both(|engine| {
+            engine.set_enabled("fueled", true);
+            engine.set_group_enabled("level1", true);
+        });
+        rules.step();
+        assert_eq!(rules.active(), ["fueled", "level_fueled"]);
+        // Only adding, replacing and removing rules recompiles the network
+        assert_eq!(rules.network.revision(), revision);
+    }
+
+    #[test]
+    fn cooldown_wake_ups() {
+        let mut rules = SideBySide::new(vec![Rule::new("mashed".to_string(), vec![more_than("presses", 5)])]);
+        rules.both(|engine| {
+            engine.set_policy("mashed", RulePolicy::default().cooldown(Duration::from_secs(10)));
+        });
+        let revision = rules.network.revision();
+        rules.store(int("presses", 6));
+        rules.step();
+        assert_eq!(rules.active(), ["mashed"]);
+
+        rules.advance_to(1);
+        rules.store(int("presses", 0));
+        rules.step();
+        rules.advance_to(2);
+        rules.store(int("presses", 7));
+        rules.step();
+        assert!(rules.active().is_empty());
+
+        // Nothing changes, the rule is only looked at again because its cooldown ends
+        rules.advance_to(9);
+        rules.step();
+        assert!(rules.active().is_empty());
+        rules.advance_to(10);
+        rules.step();
+        assert_eq!(rules.active(), ["mashed"]);
+
+        // Taking the cooldown away lets it fire straight away, without recompiling
+        rules.store(int("presses", 0));
+        rules.step();
+        rules.store(int("presses", 8));
+        rules.step();
+        assert!(rules.active().is_empty());
+        rules.both(|engine| {
+            engine.clear_policy("mashed");
+        });
+        rules.step();
+        assert_eq!(rules.active(), ["mashed"]);
+        assert_eq!(rules.network.revision(), revision);
+    }
+
+    #[test]
+    fn duplicate_and_shared_conditions() {
+        let mut rules = SideBySide::new(vec![
+            Rule::new("twice".to_string(), vec![more_than("fuel", 5), more_than("fuel", 5)]),
+            Rule::new("shared".to_string(), vec![more_than("fuel", 5), more_than("speed", 0)]),
+        ]);
+        let network = rules.network.network().unwrap();
+        assert_eq!((network.rule_count(), network.node_count()), (2, 2));
+
+        rules.store(int("fuel", 6));
+        rules.step();
+        assert_eq!(rules.active(), ["twice"]);
+        rules.store(int("speed", 1));
+        rules.step();
+        assert_eq!(rules.active(), ["shared", "twice"]);
+        rules.store(int("fuel", 5));
+        rules.step();
+        assert!(rules.active().is_empty());
+    }
+
+    #[test]
+    fn recompiles_after_the_rules_change() {
+        let mut rules = SideBySide::new(vec![Rule::new("fueled".to_string(), vec![more_than("fuel", 0)])]);
+        rules.store(int("fuel", 10));
+        rules.step();
+
+        rules.both(|engine| engine.add_rule(Rule::new("full".to_string(), vec![more_than("fuel", 50)])));
+        rules.step();
+        assert_eq!(rules.active(), ["fueled"]);
+        rules.store(int("fuel", 60));
+        rules.step();
+        assert_eq!(rules.active(), ["fueled", "full"]);
+
+        rules.both(|engine| {
+            engine.replace_rule(Rule::new("full".to_string(), vec![more_than("fuel", 90)]));
+        });
+        rules.step();
+        assert_eq!(rules.active(), ["fueled"]);
+        rules.both(|engine| {
+            engine.remove_rule("fueled");
+        });
+        rules.store(int("fuel", 95));
+        rules.step();
+        assert_eq!(rules.active(), ["full"]);
+        assert_eq!(rules.network.network().unwrap().rule_count(), 1);
+    }
+}
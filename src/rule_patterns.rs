@@ -0,0 +1,495 @@
+use std::cell::OnceCell;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::utils::hashbrown::{HashMap, HashSet};
+
+use crate::entity_facts::FactStore;
+use crate::fact_history::FactTimeline;
+use crate::fact_namespace::is_under;
+use crate::rule_policy::RuleFiring;
+use crate::rule_timers::{ConditionTimers, TimedEvaluation};
+use crate::{
+    rule_enabled, Condition, CoolFactStore, Fact, FactRemoved, FactSource, FactUpdated, Rule, RuleEngine, RuleUpdated,
+};
+
+/// Which entity each pattern variable of a rule is bound to, sorted by variable
+pub type Binding = Vec<(String, Entity)>;
+
+// The variable and the key in the entity's store of a fact name like `?ship.fuel`. A bare
+// `?ship` is the variable with an empty key.
+fn split_variable(key: &str) -> Option<(&str, &str)> {
+    if !key.starts_with('?') {
+        return None;
+    }
+    Some(key.split_once('.').unwrap_or((key, "")))
+}
+
+/// The global facts, plus the facts of the entities bound to pattern variables under the
+/// variable's name: with `?ship` bound, `?ship.fuel` is that entity's `fuel`.
+pub struct BoundFacts<'a> {
+    global: &'a dyn FactSource,
+    bound: Vec<(&'a str, &'a CoolFactStore)>,
+    // Every bound key with its variable, only listed when a namespace is looked up
+    keys: OnceCell<Vec<String>>,
+}
+
+impl<'a> BoundFacts<'a> {
+    // Constructor for BoundFacts
+    pub fn new(global: &'a dyn FactSource, bound: Vec<(&'a str, &'a CoolFactStore)>) -> Self {
+        BoundFacts { global, bound, keys: OnceCell::new() }
+    }
+
+    fn store(&self, variable: &str) -> Option<&'a CoolFactStore> {
+        self.bound.iter().find(|(bound, _)| *bound == variable).map(|(_, store)| *store)
+    }
+
+    fn keys(&self) -> &[String] {
+        self.keys.get_or_init(|| {
+            self.bound
+                .iter()
+                .flat_map(|(variable, store)| store.index.iter().map(move |key| format!("{}.{}", variable, key)))
+                .collect()
+        })
+    }
+}
+
+impl FactSource for BoundFacts<'_> {
+    fn fact(&self, key: &str) -> Option<&Fact> {
+        match split_variable(key) {
+            Some((variable, key)) => self.store(variable)?.facts.get(key),
+            None => self.global.fact(key),
+        }
+    }
+
+    fn keys_under(&self, namespace: &str) -> Vec<&str> {
+        match split_variable(namespace) {
+            Some(_) => self
+                .keys()
+                .iter()
+                .filter(|key| is_under(key, namespace))
+                .map(String::as_str)
+                .collect(),
+            None => self.global.keys_under(namespace),
+        }
+    }
+
+    fn timeline(&self, key: &str) -> Option<&FactTimeline> {
+        match split_variable(key) {
+            Some((variable, key)) => self.store(variable)?.history.timeline(key),
+            None => self.global.timeline(key),
+        }
+    }
+
+    fn now(&self) -> Duration {
+        self.global.now()
+    }
+}
+
+/// What a pattern rule knows about one binding of the variables it activates for
+#[derive(Default)]
+pub struct Activation {
+    active: bool,
+    firing: RuleFiring,
+    // Timers of duration conditions, one set for each binding of the `for_all` variables
+    timers: HashMap<Binding, ConditionTimers>,
+}
+
+impl Activation {
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+impl Rule {
+    // The pattern variables the rule reads facts through, e.g. `?ship` for `?ship.fuel`,
+    // sorted
+    pub fn variables(&self) -> Vec<&str> {
+        let mut variables: Vec<&str> = self
+            .conditions
+            .iter()
+            .flat_map(|condition| condition.fact_names())
+            .filter_map(|name| split_variable(name).map(|(variable, _)| variable))
+            .collect();
+        variables.sort();
+        variables.dedup();
+        variables
+    }
+
+    // A global rule with pattern variables, evaluated once for every binding of them
+    pub fn is_pattern(&self) -> bool {
+        !self.per_entity
+            && self.conditions.iter().flat_map(|condition| condition.fact_names()).any(|name| name.starts_with('?'))
+    }
+
+    // Make the rule hold for a binding only if it holds for every entity that `variable` can
+    // be bound to, e.g. `?pod.delivered == true` for all pods. Other variables still give
+    // one activation per binding. With no entity to bind the rule doesn't hold, so all pods
+    // aren't delivered before there are any.
+    pub fn for_all(mut self, variable: &str) -> Self {
+        self.for_all.push(variable.to_string());
+        self
+    }
+
+    // The keys the rule reads from an entity bound to `variable`
+    fn keys_read_through(&self, variable: &str) -> Vec<&str> {
+        self.conditions
+            .iter()
+            .flat_map(|condition| condition.fact_names())
+            .filter_map(|name| split_variable(name).filter(|(bound, _)| *bound == variable).map(|(_, key)| key))
+            .collect()
+    }
+}
+
+// Every way to bind the variables to distinct entities, extending `base`. An entity can be
+// bound to a variable when it has every fact the rule reads through the variable.
+fn bindings(rule: &Rule, variables: &[&str], stores: &[(Entity, &CoolFactStore)], base: &Binding) -> Vec<Binding> {
+    let mut bindings = vec![base.clone()];
+    for variable in variables {
+        let keys = rule.keys_read_through(variable);
+        let candidates: Vec<Entity> = stores
+            .iter()
+            .filter(|(_, store)| keys.iter().all(|key| key.is_empty() || store.keys_in(key).next().is_some()))
+            .map(|(entity, _)| *entity)
+            .collect();
+        bindings = bindings
+            .into_iter()
+            .flat_map(|binding| {
+                candidates
+                    .iter()
+                    .filter(|entity| !binding.iter().any(|(_, bound)| bound == *entity))
+                    .map(|entity| {
+                        let mut binding = binding.clone();
+                        binding.push((variable.to_string(), *entity));
+                        binding
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+    }
+    bindings
+}
+
+impl RuleEngine {
+    pub fn has_pattern_rules(&self) -> bool {
+        self.rules.values().any(Rule::is_pattern)
+    }
+
+    // The bindings a pattern rule is active for
+    pub fn active_bindings(&self, rule: &str) -> Vec<&Binding> {
+        let mut bindings: Vec<&Binding> = self
+            .pattern_activations
+            .get(rule)
+            .into_iter()
+            .flatten()
+            .filter(|(_, activation)| activation.active)
+            .map(|(binding, _)| binding)
+            .collect();
+        bindings.sort();
+        bindings
+    }
+
+    // Evaluate every pattern rule for every binding of its variables to the given entity
+    // stores, sorted by entity. Returns the rule, binding and new state of each activation
+    // that changed, including ones that ended because an entity went away.
+    pub fn evaluate_pattern_rules(
+        &mut self,
+        stores: &[(Entity, &CoolFactStore)],
+        global: &dyn FactSource,
+    ) -> Vec<(String, Binding, bool)> {
+        let names: Vec<String> =
+            self.rules.values().filter(|rule| rule.is_pattern()).map(|rule| rule.name.clone()).collect();
+        self.evaluate_named_pattern_rules(names, stores, global)
+    }
+
+    // Evaluate only the pattern rules that read one of the changed global keys or, through
+    // any of their variables, one of the keys changed in an entity store, or whose wake-up
+    // time has come. The entities themselves have to be the same as last time.
+    pub fn evaluate_pattern_rules_affected_by(
+        &mut self,
+        global_keys: &HashSet<String>,
+        entity_keys: &HashSet<String>,
+        stores: &[(Entity, &CoolFactStore)],
+        global: &dyn FactSource,
+    ) -> Vec<(String, Binding, bool)> {
+        let now = global.now();
+        let reads_changed = |rule: &Rule| {
+            global_keys.iter().any(|key| rule.reads_fact(key))
+                || rule.variables().iter().any(|variable| {
+                    entity_keys.iter().any(|key| rule.reads_fact(&format!("{}.{}", variable, key)))
+                })
+        };
+        let names: Vec<String> = self
+            .rules
+            .values()
+            .filter(|rule| rule.is_pattern())
+            .filter(|rule| self.pattern_wake_ups.get(&rule.name).is_some_and(|at| *at <= now) || reads_changed(rule))
+            .map(|rule| rule.name.clone())
+            .collect();
+        self.evaluate_named_pattern_rules(names, stores, global)
+    }
+
+    // The earliest time a pattern rule has to be evaluated again even if no fact changes
+    pub fn next_pattern_wake_up(&self) -> Option<Duration> {
+        self.pattern_wake_ups.values().min().copied()
+    }
+
+    fn evaluate_named_pattern_rules(
+        &mut self,
+        mut names: Vec<String>,
+        stores: &[(Entity, &CoolFactStore)],
+        global: &dyn FactSource,
+    ) -> Vec<(String, Binding, bool)> {
+        let now = global.now();
+        let mut changes = Vec::new();
+        names.sort();
+        for name in &names {
+            let rule = &self.rules[name];
+            let mut wake_up: Option<Duration> = None;
+            // Rules that time passing can't change keep no timers
+            let timed_rule = rule.conditions.iter().any(Condition::depends_on_time);
+            let enabled = rule_enabled(rule, &self.disabled_rules, &self.disabled_groups);
+            let (all_variables, each_variables): (Vec<&str>, Vec<&str>) =
+                rule.variables().into_iter().partition(|variable| rule.for_all.iter().any(|v| v == variable));
+            let activations = self.pattern_activations.entry(name.clone()).or_default();
+            let mut seen: HashSet<Binding> = HashSet::new();
+
+            for binding in bindings(rule, &each_variables, stores, &Vec::new()) {
+                let activation = activations.entry(binding.clone()).or_default();
+                // Without `for_all` variables there is exactly one full binding, the binding itself
+                let full_bindings = bindings(rule, &all_variables, stores, &binding);
+                let mut holds = enabled && !full_bindings.is_empty();
+                let mut timed_bindings: HashSet<Binding> = HashSet::new();
+                if holds {
+                    // Every binding of the `for_all` variables is evaluated, so their timers keep running
+                    for full in full_bindings {
+                        let bound = full
+                            .iter()
+                            .filter_map(|(variable, entity)| {
+                                let store = stores.iter().find(|(e, _)| e == entity)?.1;
+                                Some((variable.as_str(), store))
+                            })
+                            .collect();
+                        let facts = BoundFacts::new(global, bound);
+                        let mut untimed = ConditionTimers::new();
+                        let timers = if timed_rule {
+                            let all_binding = full[binding.len()..].to_vec();
+                            timed_bindings.insert(all_binding.clone());
+                            activation.timers.entry(all_binding).or_default()
+                        } else {
+                            &mut untimed
+                        };
+                        let mut timed = TimedEvaluation::new(timers, now);
+                        holds &= timed.rule(rule, &facts);
+                        wake_up = wake_up.into_iter().chain(timed.wake_up()).min();
+                    }
+                }
+                // Timers of `for_all` entities that went away
+                activation.timers.retain(|all_binding, _| timed_bindings.contains(all_binding));
+                let state = match self.policies.get(name) {
+                    Some(policy) => {
+                        let (state, policy_wake_up) = activation.firing.apply(policy, holds, activation.active, now);
+                        wake_up = wake_up.into_iter().chain(policy_wake_up).min();
+                        state
+                    }
+                    None => holds,
+                };
+                if state != activation.active {
+                    activation.active = state;
+                    changes.push((name.clone(), binding.clone(), state));
+                }
+                seen.insert(binding);
+            }
+
+            // Bindings whose entities went away, or stopped having the facts
+            let mut gone: Vec<Binding> =
+                activations.keys().filter(|binding| !seen.contains(*binding)).cloned().collect();
+            gone.sort();
+            for binding in gone {
+                if activations.remove(&binding).is_some_and(|activation| activation.active) {
+                    changes.push((name.clone(), binding, false));
+                }
+            }
+            let active = activations.values().any(|activation| activation.active);
+            self.rule_states.insert(name.clone(), active);
+            match wake_up {
+                Some(at) => self.pattern_wake_ups.insert(name.clone(), at),
+                None => self.pattern_wake_ups.remove(name),
+            };
+        }
+        changes
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn pattern_rule_evaluator(
+    mut rules: ResMut<RuleEngine>,
+    mut fact_updated: EventReader<FactUpdated>,
+    mut fact_removed: EventReader<FactRemoved>,
+    mut rule_updated_writer: EventWriter<RuleUpdated>,
+    storage: Res<CoolFactStore>,
+    stores: Query<(Entity, &FactStore)>,
+    added_stores: Query<Entity, Added<FactStore>>,
+    mut removed_stores: RemovedComponents<FactStore>,
+    mut seen_revision: Local<(u64, u64)>,
+) {
+    // The changed keys, kept to say which facts triggered an activation
+    let mut global_keys: HashSet<String> = HashSet::new();
+    let mut entity_keys: HashMap<Entity, HashSet<String>> = HashMap::new();
+    let changes = fact_updated.read().map(|event| (event.entity, event.fact.key().to_string()))
+        .chain(fact_removed.read().map(|event| (event.entity, event.key.clone())));
+    for (entity, key) in changes {
+        match entity {
+            Some(entity) => {
+                entity_keys.entry(entity).or_default().insert(key);
+            }
+            None => {
+                global_keys.insert(key);
+            }
+        }
+    }
+    let removed = removed_stores.read().count() > 0;
+    let stores_changed = removed || !added_stores.is_empty();
+    // Enabling, disabling and policies change states the same way rules changing does
+    let revision = (rules.revision(), rules.settings_revision());
+    let rules_changed = *seen_revision != revision;
+    *seen_revision = revision;
+    let woken = rules.next_pattern_wake_up().is_some_and(|at| at <= storage.now());
+    if !rules.has_pattern_rules()
+        || !(rules_changed || woken || stores_changed || !global_keys.is_empty() || !entity_keys.is_empty())
+    {
+        return;
+    }
+
+    let mut bound: Vec<(Entity, &CoolFactStore)> = stores.iter().map(|(entity, store)| (entity, &store.0)).collect();
+    bound.sort_by_key(|(entity, _)| *entity);
+    // Entities coming or going change the bindings of every rule
+    let changes = if rules_changed || stores_changed {
+        rules.evaluate_pattern_rules(&bound, &*storage)
+    } else {
+        let changed_entity_keys: HashSet<String> = entity_keys.values().flatten().cloned().collect();
+        rules.evaluate_pattern_rules_affected_by(&global_keys, &changed_entity_keys, &bound, &*storage)
+    };
+    for (rule, bindings, new_state) in changes {
+        let mut changed_keys = global_keys.clone();
+        for (variable, entity) in &bindings {
+            let keys = entity_keys.get(entity).into_iter().flatten();
+            changed_keys.extend(keys.map(|key| format!("{}.{}", variable, key)));
+        }
+        rule_updated_writer.send(RuleUpdated {
+            triggered_by: rules.facts_read_by(&rule, &changed_keys),
+            entity: bindings.first().map(|(_, entity)| *entity),
+            rule,
+            new_state,
+            frame: storage.frame,
+            bindings,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Condition;
+
+    fn ship(landed: bool) -> CoolFactStore {
+        let mut store = CoolFactStore::new();
+        store.store_bool("landed".to_string(), landed);
+        store
+    }
+
+    fn all_landed() -> RuleEngine {
+        let mut rules = RuleEngine::new();
+        let condition = Condition::BoolEquals { fact_name: "?ship.landed".to_string(), expected_value: true };
+        rules.add_rule(Rule::new("all_landed".to_string(), vec![condition]).for_all("?ship"));
+        rules
+    }
+
+    #[test]
+    fn for_all_needs_an_entity_to_bind() {
+        let global = CoolFactStore::new();
+        let mut rules = all_landed();
+        assert!(rules.evaluate_pattern_rules(&[], &global).is_empty());
+        assert!(!rules.is_active("all_landed"));
+
+        let (one, two) = (ship(true), ship(false));
+        let stores = [(Entity::from_raw(1), &one), (Entity::from_raw(2), &two)];
+        assert!(rules.evaluate_pattern_rules(&stores, &global).is_empty());
+        assert!(!rules.is_active("all_landed"));
+
+        let two = ship(true);
+        let stores = [(Entity::from_raw(1), &one), (Entity::from_raw(2), &two)];
+        assert_eq!(rules.evaluate_pattern_rules(&stores, &global), [("all_landed".to_string(), Vec::new(), true)]);
+
+        // The ships going away deactivates it
+        assert_eq!(rules.evaluate_pattern_rules(&[], &global), [("all_landed".to_string(), Vec::new(), false)]);
+    }
+
+    #[test]
+    fn one_activation_per_binding() {
+        let global = CoolFactStore::new();
+        let mut rules = RuleEngine::new();
+        let condition = Condition::BoolEquals { fact_name: "?ship.landed".to_string(), expected_value: true };
+        rules.add_rule(Rule::new("landed".to_string(), vec![condition]));
+
+        let (one, two) = (ship(true), ship(false));
+        let stores = [(Entity::from_raw(1), &one), (Entity::from_raw(2), &two)];
+        let binding = vec![("?ship".to_string(), Entity::from_raw(1))];
+        assert_eq!(rules.evaluate_pattern_rules(&stores, &global), [("landed".to_string(), binding.clone(), true)]);
+        assert_eq!(rules.active_bindings("landed"), [&binding]);
+    }
+
+    #[test]
+    fn timers_only_for_timed_rules_and_bindings_still_there() {
+        let global = CoolFactStore::new();
+        let mut rules = all_landed();
+        let landed = Condition::BoolEquals { fact_name: "?ship.landed".to_string(), expected_value: true };
+        let rule = Rule::new("settled".to_string(), vec![landed.held_for(Duration::from_secs(5))]).for_all("?ship");
+        rules.add_rule(rule);
+
+        let (one, two) = (ship(true), ship(true));
+        let stores = [(Entity::from_raw(1), &one), (Entity::from_raw(2), &two)];
+        rules.evaluate_pattern_rules(&stores, &global);
+        let timers = |rules: &RuleEngine, rule: &str| -> usize {
+            rules.pattern_activations[rule].values().map(|activation| activation.timers.len()).sum()
+        };
+        assert_eq!(timers(&rules, "all_landed"), 0);
+        assert_eq!(timers(&rules, "settled"), 2);
+
+        rules.evaluate_pattern_rules(&stores[..1], &global);
+        assert_eq!(timers(&rules, "settled"), 1);
+        assert_eq!(rules.next_pattern_wake_up(), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn only_rules_reading_a_changed_key_are_evaluated() {
+        let global = CoolFactStore::new();
+        let mut rules = RuleEngine::new();
+        let landed = Condition::BoolEquals { fact_name: "?ship.landed".to_string(), expected_value: true };
+        rules.add_rule(Rule::new("landed".to_string(), vec![landed]));
+        let low = Condition::IntLessThan { fact_name: "?ship.fuel".to_string(), expected_value: 10 };
+        rules.add_rule(Rule::new("low".to_string(), vec![low]));
+
+        let mut one = ship(false);
+        one.store_int("fuel".to_string(), 50);
+        rules.evaluate_pattern_rules(&[(Entity::from_raw(1), &one)], &global);
+        assert!(rules.active_bindings("landed").is_empty());
+
+        one.store_bool("landed".to_string(), true);
+        one.store_int("fuel".to_string(), 5);
+        let stores = [(Entity::from_raw(1), &one)];
+        let binding = vec![("?ship".to_string(), Entity::from_raw(1))];
+        let fuel = HashSet::from(["fuel".to_string()]);
+        assert_eq!(
+            rules.evaluate_pattern_rules_affected_by(&HashSet::new(), &fuel, &stores, &global),
+            [("low".to_string(), binding.clone(), true)]
+        );
+        // The landing wasn't among the changes, so `landed` wasn't looked at
+        assert!(rules.active_bindings("landed").is_empty());
+        let landed = HashSet::from(["landed".to_string()]);
+        assert_eq!(
+            rules.evaluate_pattern_rules_affected_by(&HashSet::new(), &landed, &stores, &global),
+            [("landed".to_string(), binding, true)]
+        );
+    }
+}
\ No newline at end of file
@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::RuleEngine;
+
+/// Limits on how often a rule can fire, that is become active. A rule without a policy
+/// fires every time its conditions become true.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RulePolicy {
+    // Fire at most this many times, ever
+    pub max_fires: Option<u32>,
+    // After firing, don't fire again until this much time has passed. A rule that is
+    // still true when the cooldown ends fires then.
+    pub cooldown: Option<Duration>,
+    // After firing, the conditions have to be false for this long before it can fire again
+    pub rearm_after: Option<Duration>,
+}
+
+impl RulePolicy {
+    // For achievements and one-off story triggers
+    pub fn fire_once() -> Self {
+        RulePolicy::default().max_fires(1)
+    }
+
+    pub fn max_fires(mut self, max_fires: u32) -> Self {
+        self.max_fires = Some(max_fires);
+        self
+    }
+
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = Some(cooldown);
+        self
+    }
+
+    pub fn rearm_after(mut self, rearm_after: Duration) -> Self {
+        self.rearm_after = Some(rearm_after);
+        self
+    }
+}
+
+/// How a rule with a policy has fired so far
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RuleFiring {
+    pub fires: u32,
+    pub last_fired: Option<Duration>,
+    // When the conditions last became false, if they still are
+    false_since: Option<Duration>,
+    // Fired, and the conditions haven't been false for `rearm_after` since
+    disarmed: bool,
+}
+
+impl RuleFiring {
+    // Whether the rule is active given its conditions, `holds`, and whether it was active
+    // before. Also returns when the rule has to be evaluated again because a cooldown ends.
+    pub fn apply(
+        &mut self,
+        policy: &RulePolicy,
+        holds: bool,
+        was_active: bool,
+        now: Duration,
+    ) -> (bool, Option<Duration>) {
+        if !holds {
+            self.false_since.get_or_insert(now);
+            return (false, None);
+        }
+        if let Some(since) = self.false_since.take() {
+            if policy.rearm_after.is_none_or(|rearm_after| now.saturating_sub(since) >= rearm_after) {
+                self.disarmed = false;
+            }
+        }
+        if was_active {
+            return (true, None);
+        }
+        if self.disarmed || policy.max_fires.is_some_and(|max_fires| self.fires >= max_fires) {
+            return (false, None);
+        }
+        if let (Some(cooldown), Some(last_fired)) = (policy.cooldown, self.last_fired) {
+            if now < last_fired + cooldown {
+                return (false, Some(last_fired + cooldown));
+            }
+        }
+        self.fires += 1;
+        self.last_fired = Some(now);
+        self.disarmed = policy.rearm_after.is_some();
+        (true, None)
+    }
+}
+
+impl RuleEngine {
+    // Set how often a rule can fire, returns false if there is no such rule. How often it
+    // has fired so far is kept.
+    pub fn set_policy(&mut self, rule: &str, policy: RulePolicy) -> bool {
+        if !self.rules.contains_key(rule) {
+            return false;
+        }
+        self.policies.insert(rule.to_string(), policy);
+        self.settings_changed(rule);
+        true
+    }
+
+    pub fn clear_policy(&mut self, rule: &str) -> Option<RulePolicy> {
+        let policy = self.policies.remove(rule)?;
+        self.settings_changed(rule);
+        Some(policy)
+    }
+
+    pub fn policy(&self, rule: &str) -> Option<&RulePolicy> {
+        self.policies.get(rule)
+    }
+
+    // How a global rule with a policy has fired so far
+    pub fn firing(&self, rule: &str) -> Option<&RuleFiring> {
+        self.firings.get(rule)
+    }
+
+    // Forget how often a rule has fired, e.g. to award an achievement again in a new game
+    pub fn reset_firing(&mut self, rule: &str) {
+        self.firings.remove(rule);
+        for firings in self.entity_firings.values_mut() {
+            firings.remove(rule);
+        }
+        self.settings_changed(rule);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Condition, CoolFactStore, Rule};
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn fire_once() {
+        let policy = RulePolicy::fire_once();
+        let mut firing = RuleFiring::default();
+        assert_eq!(firing.apply(&policy, true, false, secs(0)), (true, None));
+        // Staying true keeps it active
+        assert_eq!(firing.apply(&policy, true, true, secs(1)), (true, None));
+        assert_eq!(firing.apply(&policy, false, true, secs(2)), (false, None));
+        assert_eq!(firing.apply(&policy, true, false, secs(3)), (false, None));
+        assert_eq!(firing.fires, 1);
+        assert_eq!(firing.last_fired, Some(secs(0)));
+    }
+
+    #[test]
+    fn rearms_only_after_being_false_long_enough() {
+        let policy = RulePolicy::default().rearm_after(secs(5));
+        let mut firing = RuleFiring::default();
+        assert_eq!(firing.apply(&policy, true, false, secs(0)), (true, None));
+        assert_eq!(firing.apply(&policy, false, true, secs(1)), (false, None));
+        // False for less than `rearm_after`
+        assert_eq!(firing.apply(&policy, true, false, secs(3)), (false, None));
+        assert_eq!(firing.apply(&policy, false, false, secs(4)), (false, None));
+        // False for more than `rearm_after`
+        assert_eq!(firing.apply(&policy, true, false, secs(10)), (true, None));
+        assert_eq!(firing.fires, 2);
+    }
+
+    #[test]
+    fn cooldown_asks_to_be_evaluated_when_it_ends() {
+        let policy = RulePolicy::default().cooldown(secs(10));
+        let mut firing = RuleFiring::default();
+        assert_eq!(firing.apply(&policy, true, false, secs(0)), (true, None));
+        assert_eq!(firing.apply(&policy, false, true, secs(1)), (false, None));
+        assert_eq!(firing.apply(&policy, true, false, secs(2)), (false, Some(secs(10))));
+        assert_eq!(firing.apply(&policy, true, false, secs(10)), (true, None));
+    }
+
+    #[test]
+    fn max_fires_survives_a_save_and_load() {
+        let policy = RulePolicy::default().max_fires(2);
+        let mut firing = RuleFiring::default();
+        assert_eq!(firing.apply(&policy, true, false, secs(0)), (true, None));
+        assert_eq!(firing.apply(&policy, false, true, secs(1)), (false, None));
+        assert_eq!(firing.apply(&policy, true, false, secs(2)), (true, None));
+
+        let mut loaded: RuleFiring = ron::from_str(&ron::to_string(&firing).unwrap()).unwrap();
+        let policy: RulePolicy = ron::from_str(&ron::to_string(&policy).unwrap()).unwrap();
+        assert_eq!(loaded, firing);
+        assert_eq!(loaded.apply(&policy, false, true, secs(3)), (false, None));
+        assert_eq!(loaded.apply(&policy, true, false, secs(4)), (false, None));
+    }
+
+    #[test]
+    fn fired_rules_stay_fired_across_a_save_and_load_of_the_engine() {
+        let condition = Condition::IntMoreThan { fact_name: "score".to_string(), expected_value: 100 };
+        let mut rules = RuleEngine::new();
+        rules.add_rule(Rule::new("high_score".to_string(), vec![condition]));
+        rules.set_policy("high_score", RulePolicy::fire_once());
+        let mut storage = CoolFactStore::new();
+        storage.store_int("score".to_string(), 150);
+        rules.evaluate_rules(&storage);
+        assert!(rules.is_active("high_score"));
+
+        let mut loaded: RuleEngine = ron::from_str(&ron::to_string(&rules).unwrap()).unwrap();
+        assert_eq!(loaded.firing("high_score").map(|firing| firing.fires), Some(1));
+        storage.store_int("score".to_string(), 0);
+        loaded.evaluate_rules(&storage);
+        storage.store_int("score".to_string(), 200);
+        loaded.evaluate_rules(&storage);
+        assert!(!loaded.is_active("high_score"));
+    }
+}
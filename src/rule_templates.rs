@@ -0,0 +1,407 @@
+use std::fmt;
+
+use bevy::utils::hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{Rule, RuleEngine};
+
+/// A rule written once with parameters, e.g. `low_fuel(player, threshold)`, and expanded
+/// into a concrete `Rule` for each set of arguments. `rule` is the RON of a `Rule` where
+/// `$player` stands for the argument, inside a string as in `"$player.fuel"` or as a whole
+/// number as in `expected_value: $threshold`. Arguments go into strings escaped, and as
+/// values only if they are numbers, so they can't add to the rule. Inside a string `$$` and
+/// a `$` that doesn't start a parameter, like the end anchor of a regex, stay a `$`; raw
+/// strings are kept as they are. The name in `rule` is replaced by the instance's.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RuleTemplate {
+    pub name: String,
+    pub params: Vec<String>,
+    pub rule: String,
+}
+
+/// One use of a template, with an argument for every parameter. Without a `name` the rule
+/// is named after the template and the quoted arguments in parameter order, e.g.
+/// `low_fuel("player1","20")`, so the same instance always gets the same name and different
+/// ones never do.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TemplateInstance {
+    pub template: String,
+    pub args: HashMap<String, String>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    UnknownTemplate(String),
+    // `$param` in the rule of a template that has no such parameter
+    UnknownParameter { template: String, param: String },
+    MissingArgument { template: String, param: String },
+    UnexpectedArgument { template: String, arg: String },
+    // An argument for a parameter used as a whole value that isn't a number
+    NotANumber { template: String, param: String, value: String },
+    // The expanded rule, or a list of instances, isn't valid RON
+    Parse { template: Option<String>, error: String },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::UnknownTemplate(template) => write!(f, "no rule template {}", template),
+            TemplateError::UnknownParameter { template, param } => {
+                write!(f, "template {} uses ${} but has no parameter {}", template, param, param)
+            }
+            TemplateError::MissingArgument { template, param } => {
+                write!(f, "template {} needs an argument for {}", template, param)
+            }
+            TemplateError::UnexpectedArgument { template, arg } => {
+                write!(f, "template {} has no parameter {}", template, arg)
+            }
+            TemplateError::NotANumber { template, param, value } => {
+                write!(f, "template {} needs a number for {}, not {:?}", template, param, value)
+            }
+            TemplateError::Parse { template: Some(template), error } => {
+                write!(f, "template {} doesn't expand to a rule: {}", template, error)
+            }
+            TemplateError::Parse { template: None, error } => write!(f, "not a list of template instances: {}", error),
+        }
+    }
+}
+
+impl RuleTemplate {
+    // Constructor for RuleTemplate
+    pub fn new(name: &str, params: &[&str], rule: &str) -> Self {
+        RuleTemplate {
+            name: name.to_string(),
+            params: params.iter().map(|param| param.to_string()).collect(),
+            rule: rule.to_string(),
+        }
+    }
+
+    // Split the rule into the text to keep and the parameters it uses, failing for a
+    // parameter outside a string that isn't declared
+    fn parts(&self) -> Result<Vec<Part<'_>>, TemplateError> {
+        let rule = self.rule.as_str();
+        let mut parts = Vec::new();
+        let mut kept_from = 0;
+        let mut in_string = false;
+        let mut chars = rule.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' if in_string => {
+                    chars.next();
+                }
+                '"' => in_string = !in_string,
+                'r' if !in_string => {
+                    let rest = &rule[i + 1..];
+                    let hashes = rest.len() - rest.trim_start_matches('#').len();
+                    if !rest[hashes..].starts_with('"') {
+                        continue;
+                    }
+                    let close = format!("\"{}", "#".repeat(hashes));
+                    let body = i + 2 + hashes;
+                    let end = rule[body..].find(&close).map_or(rule.len(), |end| body + end + close.len());
+                    while chars.next_if(|(j, _)| *j < end).is_some() {}
+                }
+                '$' => {
+                    let after = &rule[i + 1..];
+                    if in_string && after.starts_with('$') {
+                        parts.push(Part::Text(&rule[kept_from..=i]));
+                        chars.next();
+                        kept_from = i + 2;
+                        continue;
+                    }
+                    let end = after.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+                    let param = &after[..end];
+                    let declared = self.params.iter().any(|declared| declared == param);
+                    if param.is_empty() || (in_string && !declared) {
+                        continue;
+                    }
+                    if !declared {
+                        return Err(TemplateError::UnknownParameter {
+                            template: self.name.clone(),
+                            param: param.to_string(),
+                        });
+                    }
+                    parts.push(Part::Text(&rule[kept_from..i]));
+                    parts.push(if in_string { Part::InString(param) } else { Part::Value(param) });
+                    while chars.next_if(|(j, _)| *j <= i + end).is_some() {}
+                    kept_from = i + 1 + end;
+                }
+                _ => {}
+            }
+        }
+        parts.push(Part::Text(&rule[kept_from..]));
+        Ok(parts)
+    }
+
+    // Expand the template into a rule
+    pub fn instantiate(&self, instance: &TemplateInstance) -> Result<Rule, TemplateError> {
+        if let Some(arg) = instance.args.keys().filter(|arg| !self.params.contains(arg)).min() {
+            return Err(TemplateError::UnexpectedArgument { template: self.name.clone(), arg: arg.clone() });
+        }
+        let args = self
+            .params
+            .iter()
+            .map(|param| {
+                let missing = || TemplateError::MissingArgument { template: self.name.clone(), param: param.clone() };
+                instance.args.get(param).map(String::as_str).ok_or_else(missing)
+            })
+            .collect::<Result<Vec<&str>, _>>()?;
+        let mut expanded = String::with_capacity(self.rule.len());
+        for part in self.parts()? {
+            match part {
+                Part::Text(text) => expanded.push_str(text),
+                Part::InString(param) => expanded.push_str(&escape(&instance.args[param])),
+                Part::Value(param) => {
+                    let value = &instance.args[param];
+                    if !is_number(value) {
+                        return Err(TemplateError::NotANumber {
+                            template: self.name.clone(),
+                            param: param.to_string(),
+                            value: value.clone(),
+                        });
+                    }
+                    expanded.push_str(value.trim());
+                }
+            }
+        }
+        let mut rule: Rule = ron::from_str(&expanded)
+            .map_err(|error| TemplateError::Parse { template: Some(self.name.clone()), error: error.to_string() })?;
+        rule.name = match &instance.name {
+            Some(name) => name.clone(),
+            None => {
+                let args: Vec<String> = args.iter().map(|arg| format!("{:?}", arg)).collect();
+                format!("{}({})", self.name, args.join(","))
+            }
+        };
+        Ok(rule)
+    }
+}
+
+// A piece of the rule of a template: text to keep as it is, or a parameter inside a string
+// or as a whole value
+enum Part<'a> {
+    Text(&'a str),
+    InString(&'a str),
+    Value(&'a str),
+}
+
+// The argument as it has to be written inside a RON string
+fn escape(arg: &str) -> String {
+    let mut escaped = String::with_capacity(arg.len());
+    for c in arg.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Whether the argument is a plain integer or decimal number, e.g. `20`, `-1.5` or `1e3`
+fn is_number(arg: &str) -> bool {
+    let arg = arg.trim();
+    arg.chars().any(|c| c.is_ascii_digit())
+        && arg.chars().all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'))
+        && arg.parse::<f64>().is_ok()
+}
+
+impl TemplateInstance {
+    // Constructor for TemplateInstance, with the arguments as parameter and value pairs
+    pub fn new(template: &str, args: &[(&str, &str)]) -> Self {
+        TemplateInstance {
+            template: template.to_string(),
+            args: args.iter().map(|(param, value)| (param.to_string(), value.to_string())).collect(),
+            name: None,
+        }
+    }
+
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+}
+
+impl RuleEngine {
+    // Add or replace a template. Fails if its rule uses a parameter it doesn't declare.
+    pub fn add_template(&mut self, template: RuleTemplate) -> Result<(), TemplateError> {
+        template.parts()?;
+        self.templates.insert(template.name.clone(), template);
+        Ok(())
+    }
+
+    pub fn template(&self, name: &str) -> Option<&RuleTemplate> {
+        self.templates.get(name)
+    }
+
+    // Expand an instance of a template, without adding the rule
+    pub fn instantiate(&self, instance: &TemplateInstance) -> Result<Rule, TemplateError> {
+        self.templates
+            .get(&instance.template)
+            .ok_or_else(|| TemplateError::UnknownTemplate(instance.template.clone()))?
+            .instantiate(instance)
+    }
+
+    // Expand an instance of a template and add the rule, returns its name
+    pub fn add_instance(&mut self, instance: &TemplateInstance) -> Result<String, TemplateError> {
+        let rule = self.instantiate(instance)?;
+        let name = rule.name.clone();
+        self.add_rule(rule);
+        Ok(name)
+    }
+
+    // Add a rule for each instance in a RON list of them, e.g. one per player of a level.
+    // Nothing is added unless every instance expands. Returns the names of the rules.
+    pub fn add_instances_from_ron(&mut self, ron: &str) -> Result<Vec<String>, TemplateError> {
+        let instances: Vec<TemplateInstance> =
+            ron::from_str(ron).map_err(|error| TemplateError::Parse { template: None, error: error.to_string() })?;
+        let rules = instances.iter().map(|instance| self.instantiate(instance)).collect::<Result<Vec<Rule>, _>>()?;
+        Ok(rules
+            .into_iter()
+            .map(|rule| {
+                let name = rule.name.clone();
+                self.add_rule(rule);
+                name
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Condition;
+
+    fn low_fuel() -> RuleTemplate {
+        RuleTemplate::new(
+            "low_fuel",
+            &["player", "threshold"],
+            r#"(name: "low_fuel", conditions: [IntLessThan(fact_name: "$player.fuel", expected_value: $threshold)])"#,
+        )
+    }
+
+    fn expand(template: &RuleTemplate, player: &str, threshold: &str) -> Result<Rule, TemplateError> {
+        template.instantiate(&TemplateInstance::new("low_fuel", &[("player", player), ("threshold", threshold)]))
+    }
+
+    #[test]
+    fn expands_strings_and_numbers() {
+        let rule = expand(&low_fuel(), "player1", "20").unwrap();
+        assert_eq!(rule.name, r#"low_fuel("player1","20")"#);
+        assert_eq!(
+            rule.conditions,
+            [Condition::IntLessThan { fact_name: "player1.fuel".to_string(), expected_value: 20 }]
+        );
+        let named = TemplateInstance::new("low_fuel", &[("player", "player2"), ("threshold", " -5 ")]).named("p2_low");
+        let rule = low_fuel().instantiate(&named).unwrap();
+        assert_eq!(rule.name, "p2_low");
+        assert_eq!(
+            rule.conditions,
+            [Condition::IntLessThan { fact_name: "player2.fuel".to_string(), expected_value: -5 }]
+        );
+    }
+
+    #[test]
+    fn arguments_cant_add_to_the_rule() {
+        let injected = r#"x.fuel", expected_value: 1), IntMoreThan(fact_name: "y"#;
+        let rule = expand(&low_fuel(), injected, "20").unwrap();
+        assert_eq!(rule.conditions.len(), 1);
+        assert_eq!(rule.conditions[0].fact_names(), [format!("{}.fuel", injected).as_str()]);
+        assert_eq!(expand(&low_fuel(), "back\\slash", "1").unwrap().conditions[0].fact_names(), ["back\\slash.fuel"]);
+
+        for threshold in ["20)]), (name: \"x\"", "", "abc", "inf", "NaN"] {
+            assert_eq!(
+                expand(&low_fuel(), "player1", threshold),
+                Err(TemplateError::NotANumber {
+                    template: "low_fuel".to_string(),
+                    param: "threshold".to_string(),
+                    value: threshold.to_string(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn dollars_in_strings_that_arent_parameters_are_kept() {
+        let template = RuleTemplate::new(
+            "callsign",
+            &["player"],
+            r#"(name: "callsign", conditions: [
+                StringMatches(fact_name: "$player.callsign", pattern: "^[A-Z]+$end|$$player$"),
+                StringEquals(fact_name: "$player.motto", expected_value: r"$player"),
+            ])"#,
+        );
+        let mut rules = RuleEngine::new();
+        rules.add_template(template).unwrap();
+        let rule = rules.instantiate(&TemplateInstance::new("callsign", &[("player", "player1")])).unwrap();
+        assert_eq!(rule.conditions[0].describe(), r"player1.callsign matches /^[A-Z]+$end|$player$/");
+        assert_eq!(rule.conditions[1].describe(), r#"player1.motto == "$player""#);
+    }
+
+    #[test]
+    fn errors() {
+        let mut rules = RuleEngine::new();
+        let rule = r#"(name: "bad", conditions: [IntLessThan(fact_name: "x", expected_value: $max)])"#;
+        let unknown = RuleTemplate::new("bad", &[], rule);
+        assert_eq!(
+            rules.add_template(unknown),
+            Err(TemplateError::UnknownParameter { template: "bad".to_string(), param: "max".to_string() })
+        );
+        rules.add_template(low_fuel()).unwrap();
+
+        let missing = TemplateInstance::new("low_fuel", &[("player", "player1")]);
+        assert_eq!(
+            rules.instantiate(&missing),
+            Err(TemplateError::MissingArgument { template: "low_fuel".to_string(), param: "threshold".to_string() })
+        );
+        let extra = TemplateInstance::new("low_fuel", &[("player", "p"), ("threshold", "1"), ("ship", "s")]);
+        assert_eq!(
+            rules.instantiate(&extra),
+            Err(TemplateError::UnexpectedArgument { template: "low_fuel".to_string(), arg: "ship".to_string() })
+        );
+        assert_eq!(
+            rules.instantiate(&TemplateInstance::new("high_fuel", &[])),
+            Err(TemplateError::UnknownTemplate("high_fuel".to_string()))
+        );
+        // Too big for an i32
+        assert!(matches!(expand(&low_fuel(), "player1", "1e12"), Err(TemplateError::Parse { .. })));
+
+        // Nothing is added unless every instance expands
+        let instances = r#"[
+            (template: "low_fuel", args: {"player": "player1", "threshold": "20"}),
+            (template: "low_fuel", args: {"player": "player2"}),
+        ]"#;
+        assert!(rules.add_instances_from_ron(instances).is_err());
+        assert!(rules.rules.is_empty());
+        assert!(matches!(rules.add_instances_from_ron("[("), Err(TemplateError::Parse { template: None, .. })));
+    }
+
+    #[test]
+    fn names_are_stable_and_distinct() {
+        let template = RuleTemplate::new(
+            "pair",
+            &["a", "b"],
+            r#"(name: "pair", conditions: [StringEquals(fact_name: "$a", expected_value: "$b")])"#,
+        );
+        let name = |a: &str, b: &str| {
+            template.instantiate(&TemplateInstance::new("pair", &[("b", b), ("a", a)])).unwrap().name
+        };
+        assert_eq!(name("x", "y"), name("x", "y"));
+        assert_eq!(name("x", "y"), r#"pair("x","y")"#);
+        assert_ne!(name("a,b", "c"), name("a", "b,c"));
+        assert_ne!(name(r#"a",""#, "b"), name("a", r#"","b"#));
+
+        let mut rules = RuleEngine::new();
+        rules.add_template(template.clone()).unwrap();
+        let instances = r#"[
+            (template: "pair", args: {"a": "one", "b": "two"}),
+            (template: "pair", args: {"a": "one", "b": "two"}, name: Some("mine")),
+        ]"#;
+        assert_eq!(rules.add_instances_from_ron(instances).unwrap(), [r#"pair("one","two")"#, "mine"]);
+    }
+}
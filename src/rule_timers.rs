@@ -0,0 +1,246 @@
+use std::time::Duration;
+
+use bevy::utils::hashbrown::HashMap;
+
+use crate::fact_namespace::{child_namespaces, ScopedFacts};
+use crate::{Condition, FactSource, Rule};
+
+/// Timer state of one `HeldFor` or `NotWithin` condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConditionTimer {
+    // When the wrapped condition became true, if it still is
+    true_since: Option<Duration>,
+    // When the wrapped condition was last true, or when the timer started if it never was
+    last_true: Duration,
+}
+
+impl ConditionTimer {
+    // Constructor for ConditionTimer
+    pub fn new(now: Duration) -> Self {
+        ConditionTimer {
+            true_since: None,
+            last_true: now,
+        }
+    }
+
+    // Record what the wrapped condition is now
+    fn observe(&mut self, holds: bool, now: Duration) {
+        if holds {
+            self.true_since.get_or_insert(now);
+            self.last_true = now;
+        } else if self.true_since.take().is_some() {
+            // It was true right up until now
+            self.last_true = now;
+        }
+    }
+
+    // How long the wrapped condition has been true for, if it is
+    pub fn held_for(&self, now: Duration) -> Option<Duration> {
+        self.true_since.map(|since| now.saturating_sub(since))
+    }
+
+    // How long ago the wrapped condition was last true, zero if it is true now
+    pub fn since_last_true(&self, now: Duration) -> Duration {
+        match self.true_since {
+            Some(_) => Duration::ZERO,
+            None => now.saturating_sub(self.last_true),
+        }
+    }
+}
+
+/// Timers of duration conditions, keyed by rule name and the position of the condition in
+/// the rule, e.g. `stranded_rule:0` or `any_pod_rule:1/pods.one:0`
+pub type ConditionTimers = HashMap<String, ConditionTimer>;
+
+/// Evaluates rules with their duration conditions, and works out when the outcome can next
+/// change just because time passes, so the rule only has to be looked at again then.
+pub struct TimedEvaluation<'a> {
+    timers: &'a mut ConditionTimers,
+    now: Duration,
+    wake_up: Option<Duration>,
+}
+
+impl<'a> TimedEvaluation<'a> {
+    // Constructor for TimedEvaluation
+    pub fn new(timers: &'a mut ConditionTimers, now: Duration) -> Self {
+        TimedEvaluation {
+            timers,
+            now,
+            wake_up: None,
+        }
+    }
+
+    // The earliest time any condition evaluated so far needs to be looked at again
+    pub fn wake_up(&self) -> Option<Duration> {
+        self.wake_up
+    }
+
+    fn schedule(&mut self, at: Duration) {
+        self.wake_up = Some(self.wake_up.map_or(at, |wake_up| wake_up.min(at)));
+    }
+
+    // Evaluate every condition of the rule. Unlike `Rule::evaluate` this doesn't stop at the
+    // first false condition, so every timer sees the current facts.
+    pub fn rule(&mut self, rule: &Rule, facts: &dyn FactSource) -> bool {
+        let mut holds = true;
+        for (index, condition) in rule.conditions.iter().enumerate() {
+            holds &= self.condition(condition, facts, &format!("{}:{}", rule.name, index));
+        }
+        holds
+    }
+
+    fn condition(&mut self, condition: &Condition, facts: &dyn FactSource, path: &str) -> bool {
+        match condition {
+            Condition::HeldFor { condition: inner, duration } | Condition::NotWithin { condition: inner, duration } => {
+                let inner_holds = self.condition(inner, facts, &format!("{}:0", path));
+                let now = self.now;
+                let timer = self.timers.entry(path.to_string()).or_insert_with(|| ConditionTimer::new(now));
+                timer.observe(inner_holds, now);
+                let (holds, wake_up) = match condition {
+                    Condition::HeldFor { .. } => match timer.held_for(now) {
+                        Some(held) if held >= *duration => (true, None),
+                        Some(held) => (false, Some(now + (*duration - held))),
+                        None => (false, None),
+                    },
+                    _ => match timer.since_last_true(now) {
+                        _ if inner_holds => (false, None),
+                        since if since >= *duration => (true, None),
+                        since => (false, Some(now + (*duration - since))),
+                    },
+                };
+                if let Some(at) = wake_up {
+                    self.schedule(at);
+                }
+                holds
+            }
+            Condition::AnyUnder { namespace, condition: inner } | Condition::AllUnder { namespace, condition: inner } => {
+                // Every child is evaluated, so their timers keep running
+                let children: Vec<bool> = child_namespaces(facts, namespace)
+                    .into_iter()
+                    .map(|child| {
+                        let path = format!("{}/{}", path, child);
+                        self.condition(inner, &ScopedFacts::new(facts, child), &path)
+                    })
+                    .collect();
+                match condition {
+                    Condition::AnyUnder { .. } => children.iter().any(|holds| *holds),
+                    _ => !children.is_empty() && children.iter().all(|holds| *holds),
+                }
+            }
+            _ => {
+                if let Some(at) = condition.next_change(facts) {
+                    self.schedule(at);
+                }
+                condition.evaluate(facts)
+            }
+        }
+    }
+}
+
+impl Condition {
+    // True once this condition has held for `duration` without a break
+    pub fn held_for(self, duration: Duration) -> Condition {
+        Condition::HeldFor { condition: Box::new(self), duration }
+    }
+
+    // True once this condition hasn't held for `duration`
+    pub fn not_within(self, duration: Duration) -> Condition {
+        Condition::NotWithin { condition: Box::new(self), duration }
+    }
+
+    // When a time-windowed condition can next change just because time passes, if it can.
+    // At that moment it can still have its old value, so it may ask to be looked at again.
+    pub fn next_change(&self, facts: &dyn FactSource) -> Option<Duration> {
+        let now = facts.now();
+        match self {
+            Condition::ChangedWithin { fact_name, within } => {
+                let last = facts.timeline(fact_name)?.last_change()?.elapsed;
+                (now.saturating_sub(last) <= *within).then_some(last + *within)
+            }
+            Condition::ChangeCountAtLeast { fact_name, count, within } => {
+                // True until the oldest of the last `count` changes leaves the window
+                let timeline = facts.timeline(fact_name)?;
+                let oldest = timeline
+                    .changes()
+                    .rev()
+                    .take_while(|change| now.saturating_sub(change.elapsed) <= *within)
+                    .nth(count.checked_sub(1)?)?;
+                Some(oldest.elapsed + *within)
+            }
+            Condition::UnchangedFor { fact_name, duration } => {
+                let last = facts.timeline(fact_name)?.last_change()?.elapsed;
+                (now.saturating_sub(last) < *duration).then_some(last + *duration)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CoolFactStore;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn fuel(value: i32) -> CoolFactStore {
+        let mut store = CoolFactStore::new();
+        store.store_int("fuel".to_string(), value);
+        store
+    }
+
+    // Whether the rule holds at `now`, and when it asks to be looked at again
+    fn step(timers: &mut ConditionTimers, rule: &Rule, facts: &CoolFactStore, now: u64) -> (bool, Option<Duration>) {
+        let mut evaluation = TimedEvaluation::new(timers, secs(now));
+        let holds = evaluation.rule(rule, facts);
+        (holds, evaluation.wake_up())
+    }
+
+    #[test]
+    fn held_for_waits_for_the_duration_without_a_break() {
+        let empty = Condition::IntLessThan { fact_name: "fuel".to_string(), expected_value: 1 };
+        let rule = Rule::new("stranded".to_string(), vec![empty.held_for(secs(5))]);
+        let mut timers = ConditionTimers::new();
+
+        assert_eq!(step(&mut timers, &rule, &fuel(0), 0), (false, Some(secs(5))));
+        assert_eq!(step(&mut timers, &rule, &fuel(0), 3), (false, Some(secs(5))));
+        assert_eq!(step(&mut timers, &rule, &fuel(0), 5), (true, None));
+        assert_eq!(step(&mut timers, &rule, &fuel(10), 6), (false, None));
+        // Running dry again starts over
+        assert_eq!(step(&mut timers, &rule, &fuel(0), 7), (false, Some(secs(12))));
+        assert_eq!(step(&mut timers, &rule, &fuel(0), 12), (true, None));
+        assert!(timers.contains_key("stranded:0"));
+    }
+
+    #[test]
+    fn not_within_waits_for_the_duration_after_the_condition_stops() {
+        let full = Condition::IntMoreThan { fact_name: "fuel".to_string(), expected_value: 50 };
+        let rule = Rule::new("running_low".to_string(), vec![full.not_within(secs(2))]);
+        let mut timers = ConditionTimers::new();
+
+        // The timer starts when the rule is first looked at
+        assert_eq!(step(&mut timers, &rule, &fuel(10), 0), (false, Some(secs(2))));
+        assert_eq!(step(&mut timers, &rule, &fuel(10), 2), (true, None));
+        assert_eq!(step(&mut timers, &rule, &fuel(100), 3), (false, None));
+        assert_eq!(step(&mut timers, &rule, &fuel(40), 4), (false, Some(secs(6))));
+        assert_eq!(step(&mut timers, &rule, &fuel(40), 5), (false, Some(secs(6))));
+        assert_eq!(step(&mut timers, &rule, &fuel(40), 6), (true, None));
+    }
+
+    #[test]
+    fn wakes_up_for_the_earliest_condition() {
+        let empty = Condition::IntLessThan { fact_name: "fuel".to_string(), expected_value: 1 };
+        let rule = Rule::new(
+            "stranded".to_string(),
+            vec![empty.clone().held_for(secs(5)), empty.held_for(secs(2))],
+        );
+        let mut timers = ConditionTimers::new();
+
+        assert_eq!(step(&mut timers, &rule, &fuel(0), 0), (false, Some(secs(2))));
+        assert_eq!(step(&mut timers, &rule, &fuel(0), 2), (false, Some(secs(5))));
+        assert_eq!(step(&mut timers, &rule, &fuel(0), 5), (true, None));
+        assert_eq!(timers.len(), 2);
+    }
+}
@@ -0,0 +1,537 @@
+use std::fmt;
+
+use bevy::utils::hashbrown::HashMap;
+
+use crate::fact_expr::Comparison;
+use crate::{Condition, CoolFactStore, FactKind, Rule, RuleEngine, StoryEngine};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Where a problem was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticLocation {
+    Rule(String),
+    StoryBeat { story: String, beat: String, rule: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    // A condition reads a fact that has no declared default and isn't in the store
+    UndeclaredFact { fact: String },
+    // Conditions of the same rule on one fact that can't all hold at once
+    Contradiction { fact: String, conditions: Vec<String> },
+    // A single condition that can never be true
+    Unsatisfiable { condition: String, reason: String },
+    DuplicateRule { name: String },
+    // An earlier beat of the story can never finish
+    UnreachableBeat { blocked_by: String },
+    // A pattern variable that is never bound to an entity, or `for_all` on a variable no
+    // condition reads
+    UnboundVariable { variable: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: DiagnosticLocation,
+    pub kind: DiagnosticKind,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: ", severity)?;
+        match &self.location {
+            DiagnosticLocation::Rule(rule) => write!(f, "rule {}: ", rule)?,
+            DiagnosticLocation::StoryBeat { story, beat, rule: Some(rule) } => {
+                write!(f, "story {}, beat {}, rule {}: ", story, beat, rule)?
+            }
+            DiagnosticLocation::StoryBeat { story, beat, rule: None } => write!(f, "story {}, beat {}: ", story, beat)?,
+        }
+        match &self.kind {
+            DiagnosticKind::UndeclaredFact { fact } => write!(f, "fact {} is never declared", fact),
+            DiagnosticKind::Contradiction { fact, conditions } => {
+                write!(f, "conditions on {} contradict each other: {}", fact, conditions.join(", "))
+            }
+            DiagnosticKind::Unsatisfiable { condition, reason } => write!(f, "{} can never be true, {}", condition, reason),
+            DiagnosticKind::DuplicateRule { name } => write!(f, "rule {} is added more than once", name),
+            DiagnosticKind::UnreachableBeat { blocked_by } => {
+                write!(f, "can't be reached because beat {} can never finish", blocked_by)
+            }
+            DiagnosticKind::UnboundVariable { variable } => write!(f, "pattern variable {} is never bound", variable),
+        }
+    }
+}
+
+// Whether `key`, read from inside the given `AnyUnder`/`AllUnder` namespaces, matches a
+// known key. Each namespace is followed by one child segment, which can be anything.
+fn matches_scoped(known: &str, scopes: &[&str], key: &str) -> bool {
+    let mut pattern: Vec<&str> = Vec::new();
+    for scope in scopes {
+        pattern.extend(scope.split('.'));
+        pattern.push("*");
+    }
+    pattern.extend(key.split('.'));
+    let segments: Vec<&str> = known.split('.').collect();
+    segments.len() == pattern.len()
+        && segments.iter().zip(&pattern).all(|(segment, pattern)| *pattern == "*" || segment == pattern)
+}
+
+// The fact a value condition reads and the type it needs
+fn value_condition(condition: &Condition) -> Option<(&str, FactKind)> {
+    match condition {
+        Condition::IntEquals { fact_name, .. }
+        | Condition::IntMoreThan { fact_name, .. }
+        | Condition::IntLessThan { fact_name, .. } => Some((fact_name, FactKind::Int)),
+        Condition::StringEquals { fact_name, .. }
+        | Condition::StringStartsWith { fact_name, .. }
+        | Condition::StringEndsWith { fact_name, .. }
+        | Condition::StringContains { fact_name, .. }
+        | Condition::StringEqualsIgnoreCase { fact_name, .. }
+        | Condition::StringMatches { fact_name, .. }
+        | Condition::StringIn { fact_name, .. } => Some((fact_name, FactKind::String)),
+        Condition::BoolEquals { fact_name, .. } => Some((fact_name, FactKind::Bool)),
+        Condition::ListContains { fact_name, .. }
+        | Condition::ListSize { fact_name, .. }
+        | Condition::ListSubsetOf { fact_name, .. }
+        | Condition::ListSupersetOf { fact_name, .. }
+        | Condition::ListIntersects { fact_name, .. } => Some((fact_name, FactKind::StringList)),
+        Condition::FloatMoreThan { fact_name, .. } | Condition::FloatLessThan { fact_name, .. } => {
+            Some((fact_name, FactKind::Float))
+        }
+        _ => None,
+    }
+}
+
+struct Validator<'a> {
+    storage: &'a CoolFactStore,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Validator<'a> {
+    fn is_known(&self, scopes: &[&str], key: &str) -> bool {
+        // Facts read through a pattern variable live in the stores of entities
+        if key.starts_with('?') {
+            return true;
+        }
+        if scopes.is_empty() {
+            return self.storage.defaults.contains_key(key) || self.storage.facts.contains_key(key);
+        }
+        self.storage
+            .defaults
+            .keys()
+            .chain(self.storage.facts.keys())
+            .any(|known| matches_scoped(known, scopes, key))
+    }
+
+    fn report(&mut self, severity: Severity, location: &DiagnosticLocation, kind: DiagnosticKind) {
+        self.diagnostics.push(Diagnostic { severity, location: location.clone(), kind });
+    }
+
+    // Report undeclared facts and unsatisfiable conditions, returns false if the condition
+    // can never be true. `global` is false for per-entity rules, whose facts and history
+    // live in the entity's own store, so they aren't checked against the declared ones.
+    fn check_condition(
+        &mut self,
+        condition: &Condition,
+        scopes: &[&str],
+        global: bool,
+        location: &DiagnosticLocation,
+    ) -> bool {
+        let unsatisfiable = |reason: String| DiagnosticKind::Unsatisfiable { condition: condition.describe(), reason };
+        match condition {
+            Condition::AnyUnder { namespace, condition: inner } | Condition::AllUnder { namespace, condition: inner } => {
+                let mut inner_scopes = scopes.to_vec();
+                inner_scopes.push(namespace);
+                return self.check_condition(inner, &inner_scopes, global, location);
+            }
+            Condition::HeldFor { condition: inner, .. } | Condition::NotWithin { condition: inner, .. } => {
+                let satisfiable = self.check_condition(inner, scopes, global, location);
+                if matches!(location, DiagnosticLocation::StoryBeat { .. }) {
+                    let reason = "duration conditions are only timed in rules of the rule engine".to_string();
+                    self.report(Severity::Error, location, unsatisfiable(reason));
+                    return false;
+                }
+                // A condition that never holds is never held, but is always not within
+                return satisfiable || matches!(condition, Condition::NotWithin { .. });
+            }
+            Condition::HasFactsUnder { namespace } => {
+                // Any known key that is the namespace or sits below it
+                let known = self.storage.defaults.keys().chain(self.storage.facts.keys()).any(|key| {
+                    key.match_indices('.')
+                        .map(|(i, _)| &key[..i])
+                        .chain([key.as_str()])
+                        .any(|prefix| matches_scoped(prefix, scopes, namespace))
+                });
+                if global && !known {
+                    self.report(Severity::Warning, location, DiagnosticKind::UndeclaredFact { fact: namespace.clone() });
+                }
+                return true;
+            }
+            Condition::ChangedWithin { fact_name, .. }
+            | Condition::ChangeCountAtLeast { fact_name, .. }
+            | Condition::UnchangedFor { fact_name, .. } => {
+                if !global || !scopes.is_empty() || fact_name.starts_with('?') {
+                    return true;
+                }
+                let Some(timeline) = self.storage.history.timeline(fact_name) else {
+                    let reason = format!("the history of {} isn't tracked", fact_name);
+                    self.report(Severity::Error, location, unsatisfiable(reason));
+                    return false;
+                };
+                if let Condition::ChangeCountAtLeast { count, .. } = condition {
+                    if *count > timeline.capacity() {
+                        let reason = format!("only {} changes of {} are kept", timeline.capacity(), fact_name);
+                        self.report(Severity::Error, location, unsatisfiable(reason));
+                        return false;
+                    }
+                }
+                return true;
+            }
+            Condition::Compare { .. } => {
+                let mut satisfiable = true;
+                for fact_name in condition.fact_names() {
+                    if global && !self.is_known(scopes, fact_name) {
+                        let kind = DiagnosticKind::UndeclaredFact { fact: fact_name.to_string() };
+                        self.report(Severity::Warning, location, kind);
+                    }
+                    let declared =
+                        (global && scopes.is_empty()).then(|| self.storage.defaults.get(fact_name)).flatten();
+                    if let Some(default) = declared.filter(|default| default.as_f32().is_none()) {
+                        let reason = format!("{} is declared as {:?}, not a number", fact_name, default.kind());
+                        self.report(Severity::Error, location, unsatisfiable(reason));
+                        satisfiable = false;
+                    }
+                }
+                return satisfiable;
+            }
+            _ => {}
+        }
+
+        let Some((fact_name, kind)) = value_condition(condition) else {
+            return true;
+        };
+        if global && !self.is_known(scopes, fact_name) {
+            self.report(Severity::Warning, location, DiagnosticKind::UndeclaredFact { fact: fact_name.to_string() });
+        }
+        if global && scopes.is_empty() {
+            if let Some(default) = self.storage.defaults.get(fact_name) {
+                if default.kind() != kind {
+                    let reason = format!("{} is declared as {:?}", fact_name, default.kind());
+                    self.report(Severity::Error, location, unsatisfiable(reason));
+                    return false;
+                }
+            }
+        }
+        let out_of_range = "no value is out of that range";
+        let impossible = match condition {
+            Condition::IntMoreThan { expected_value, .. } => (*expected_value == i32::MAX).then_some(out_of_range),
+            Condition::IntLessThan { expected_value, .. } => (*expected_value == i32::MIN).then_some(out_of_range),
+            Condition::FloatMoreThan { expected_value, .. } => {
+                (expected_value.0.is_nan() || expected_value.0 == f32::INFINITY).then_some(out_of_range)
+            }
+            Condition::FloatLessThan { expected_value, .. } => {
+                (expected_value.0.is_nan() || expected_value.0 == f32::NEG_INFINITY).then_some(out_of_range)
+            }
+            Condition::StringIn { values, .. } | Condition::ListIntersects { values, .. } => {
+                values.is_empty().then_some("there are no values to match")
+            }
+            Condition::ListSize { comparison: Comparison::Less, size: 0, .. } => {
+                Some("no list has fewer than 0 items")
+            }
+            _ => None,
+        };
+        if let Some(reason) = impossible {
+            self.report(Severity::Error, location, unsatisfiable(reason.to_string()));
+        }
+        impossible.is_none()
+    }
+
+    // Report conditions on the same fact that can't hold together, returns false if any do
+    fn check_contradictions(&mut self, rule: &Rule, location: &DiagnosticLocation) -> bool {
+        let mut by_fact: HashMap<&str, Vec<&Condition>> = HashMap::new();
+        for condition in &rule.conditions {
+            if let Some((fact_name, _)) = value_condition(condition) {
+                by_fact.entry(fact_name).or_default().push(condition);
+            }
+        }
+        let mut facts: Vec<&str> = by_fact.keys().copied().collect();
+        facts.sort();
+
+        let mut consistent = true;
+        for fact in facts {
+            let conditions = &by_fact[fact];
+            if conditions.len() < 2 || !contradict(conditions) {
+                continue;
+            }
+            consistent = false;
+            self.report(
+                Severity::Error,
+                location,
+                DiagnosticKind::Contradiction {
+                    fact: fact.to_string(),
+                    conditions: conditions.iter().map(|condition| condition.describe()).collect(),
+                },
+            );
+        }
+        consistent
+    }
+
+    // Check every condition of the rule, returns false if the rule can never be true
+    fn check_rule(&mut self, rule: &Rule, location: &DiagnosticLocation) -> bool {
+        let mut satisfiable = self.check_contradictions(rule, location);
+        let variables = rule.variables();
+        for variable in &rule.for_all {
+            if !variables.contains(&variable.as_str()) {
+                self.report(Severity::Warning, location, DiagnosticKind::UnboundVariable { variable: variable.clone() });
+            }
+        }
+        // Stories evaluate their rules against the global store only
+        if rule.is_pattern() && matches!(location, DiagnosticLocation::StoryBeat { .. }) {
+            for variable in variables {
+                self.report(Severity::Error, location, DiagnosticKind::UnboundVariable { variable: variable.to_string() });
+            }
+            satisfiable = false;
+        }
+        for condition in &rule.conditions {
+            satisfiable &= self.check_condition(condition, &[], !rule.per_entity, location);
+        }
+        satisfiable
+    }
+}
+
+// Whether value conditions on one fact can't all be true for any value
+fn contradict(conditions: &[&Condition]) -> bool {
+    let kinds: Vec<FactKind> = conditions.iter().filter_map(|c| value_condition(c)).map(|(_, kind)| kind).collect();
+    if kinds.windows(2).any(|pair| pair[0] != pair[1]) {
+        return true;
+    }
+
+    let (mut int_low, mut int_high) = (i64::MIN, i64::MAX);
+    let (mut float_low, mut float_high) = (f32::NEG_INFINITY, f32::INFINITY);
+    let mut string_value: Option<&String> = None;
+    let mut bool_value: Option<bool> = None;
+    for condition in conditions {
+        match condition {
+            Condition::IntEquals { expected_value, .. } => {
+                int_low = int_low.max(*expected_value as i64);
+                int_high = int_high.min(*expected_value as i64);
+            }
+            Condition::IntMoreThan { expected_value, .. } => int_low = int_low.max(*expected_value as i64 + 1),
+            Condition::IntLessThan { expected_value, .. } => int_high = int_high.min(*expected_value as i64 - 1),
+            Condition::FloatMoreThan { expected_value, .. } => float_low = float_low.max(expected_value.0),
+            Condition::FloatLessThan { expected_value, .. } => float_high = float_high.min(expected_value.0),
+            Condition::StringEquals { expected_value, .. } => {
+                if string_value.is_some_and(|value| value != expected_value) {
+                    return true;
+                }
+                string_value = Some(expected_value);
+            }
+            Condition::BoolEquals { expected_value, .. } => {
+                if bool_value.is_some_and(|value| value != *expected_value) {
+                    return true;
+                }
+                bool_value = Some(*expected_value);
+            }
+            _ => {}
+        }
+    }
+    int_low > int_high || float_low >= float_high
+}
+
+// Check the rules and stories against the facts declared in `storage`. Diagnostics come
+// back sorted by rule name, then stories in the order they were added.
+pub fn validate(rules: &RuleEngine, stories: &StoryEngine, storage: &CoolFactStore) -> Vec<Diagnostic> {
+    let mut validator = Validator { storage, diagnostics: Vec::new() };
+
+    let mut duplicates: Vec<&String> = rules.duplicate_rules().iter().collect();
+    duplicates.sort();
+    duplicates.dedup();
+    for name in duplicates {
+        validator.report(
+            Severity::Error,
+            &DiagnosticLocation::Rule(name.clone()),
+            DiagnosticKind::DuplicateRule { name: name.clone() },
+        );
+    }
+
+    let mut names: Vec<&String> = rules.rules.keys().collect();
+    names.sort();
+    for name in names {
+        validator.check_rule(&rules.rules[name], &DiagnosticLocation::Rule(name.clone()));
+    }
+
+    for story in &stories.stories {
+        let mut blocked_by: Option<String> = None;
+        for beat in &story.beats {
+            if let Some(blocked_by) = &blocked_by {
+                validator.report(
+                    Severity::Error,
+                    &DiagnosticLocation::StoryBeat { story: story.name.clone(), beat: beat.name.clone(), rule: None },
+                    DiagnosticKind::UnreachableBeat { blocked_by: blocked_by.clone() },
+                );
+                continue;
+            }
+            let mut can_finish = true;
+            for rule in &beat.rules {
+                let location = DiagnosticLocation::StoryBeat {
+                    story: story.name.clone(),
+                    beat: beat.name.clone(),
+                    rule: Some(rule.name.clone()),
+                };
+                can_finish &= validator.check_rule(rule, &location);
+            }
+            if !can_finish {
+                blocked_by = Some(beat.name.clone());
+            }
+        }
+    }
+
+    validator.diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Fact, Story, StoryBeat};
+
+    fn int_less_than(fact_name: &str, expected_value: i32) -> Condition {
+        Condition::IntLessThan { fact_name: fact_name.to_string(), expected_value }
+    }
+
+    fn kinds(rules: &RuleEngine, stories: &StoryEngine, storage: &CoolFactStore) -> Vec<DiagnosticKind> {
+        validate(rules, stories, storage).into_iter().map(|diagnostic| diagnostic.kind).collect()
+    }
+
+    #[test]
+    fn undeclared_fact_only_in_global_rules() {
+        let mut storage = CoolFactStore::new();
+        storage.declare_fact(Fact::Int("lives".to_string(), 3));
+        let mut rules = RuleEngine::new();
+        rules.add_rule(Rule::new("game_over".to_string(), vec![int_less_than("lives", 1)]));
+        rules.add_rule(Rule::new("low_fuel".to_string(), vec![int_less_than("fuel", 10)]));
+        // Ships keep fuel in their own store
+        rules.add_rule(Rule::new("ship_low_fuel".to_string(), vec![int_less_than("fuel", 10)]).per_entity());
+
+        let diagnostics = validate(&rules, &StoryEngine::new(), &storage);
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                severity: Severity::Warning,
+                location: DiagnosticLocation::Rule("low_fuel".to_string()),
+                kind: DiagnosticKind::UndeclaredFact { fact: "fuel".to_string() },
+            }]
+        );
+    }
+
+    #[test]
+    fn contradiction() {
+        let mut storage = CoolFactStore::new();
+        storage.declare_fact(Fact::Int("fuel".to_string(), 100));
+        let mut rules = RuleEngine::new();
+        let conditions = vec![
+            Condition::IntMoreThan { fact_name: "fuel".to_string(), expected_value: 50 },
+            int_less_than("fuel", 20),
+        ];
+        rules.add_rule(Rule::new("fuel".to_string(), conditions.clone()));
+
+        assert_eq!(
+            kinds(&rules, &StoryEngine::new(), &storage),
+            [DiagnosticKind::Contradiction {
+                fact: "fuel".to_string(),
+                conditions: conditions.iter().map(Condition::describe).collect(),
+            }]
+        );
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        let mut storage = CoolFactStore::new();
+        storage.declare_fact(Fact::Bool("landed".to_string(), false));
+        let mut rules = RuleEngine::new();
+        let condition = int_less_than("landed", 1);
+        rules.add_rule(Rule::new("landed".to_string(), vec![condition.clone()]));
+
+        assert_eq!(
+            kinds(&rules, &StoryEngine::new(), &storage),
+            [DiagnosticKind::Unsatisfiable {
+                condition: condition.describe(),
+                reason: "landed is declared as Bool".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn duplicate_rule() {
+        let mut storage = CoolFactStore::new();
+        storage.declare_fact(Fact::Int("lives".to_string(), 3));
+        let mut rules = RuleEngine::new();
+        rules.add_rule(Rule::new("game_over".to_string(), vec![int_less_than("lives", 1)]));
+        rules.add_rule(Rule::new("game_over".to_string(), vec![int_less_than("lives", 2)]));
+
+        assert_eq!(
+            kinds(&rules, &StoryEngine::new(), &storage),
+            [DiagnosticKind::DuplicateRule { name: "game_over".to_string() }]
+        );
+    }
+
+    #[test]
+    fn unreachable_beat() {
+        let mut storage = CoolFactStore::new();
+        storage.declare_fact(Fact::Int("age".to_string(), 25));
+        let mut stories = StoryEngine::new();
+        let never = Rule::new("never".to_string(), vec![int_less_than("age", i32::MIN)]);
+        let young = Rule::new("young".to_string(), vec![int_less_than("age", 30)]);
+        stories.add_story(Story::new(
+            "story".to_string(),
+            vec![
+                StoryBeat::new("first".to_string(), vec![never]),
+                StoryBeat::new("second".to_string(), vec![young]),
+            ],
+        ));
+
+        let diagnostics = validate(&RuleEngine::new(), &stories, &storage);
+        assert_eq!(diagnostics.len(), 2);
+        assert!(matches!(diagnostics[0].kind, DiagnosticKind::Unsatisfiable { .. }));
+        assert_eq!(
+            diagnostics[1],
+            Diagnostic {
+                severity: Severity::Error,
+                location: DiagnosticLocation::StoryBeat {
+                    story: "story".to_string(),
+                    beat: "second".to_string(),
+                    rule: None,
+                },
+                kind: DiagnosticKind::UnreachableBeat { blocked_by: "first".to_string() },
+            }
+        );
+    }
+
+    #[test]
+    fn unbound_variable() {
+        let mut storage = CoolFactStore::new();
+        storage.declare_fact(Fact::Int("lives".to_string(), 3));
+        let mut rules = RuleEngine::new();
+        let condition = int_less_than("lives", 1);
+        rules.add_rule(Rule::new("game_over".to_string(), vec![condition.clone()]).for_all("?ship"));
+        let landed = Condition::BoolEquals { fact_name: "?ship.landed".to_string(), expected_value: true };
+        let mut stories = StoryEngine::new();
+        let beat = StoryBeat::new("landing".to_string(), vec![Rule::new("landed".to_string(), vec![landed])]);
+        stories.add_story(Story::new("story".to_string(), vec![beat]));
+
+        assert_eq!(
+            kinds(&rules, &stories, &storage),
+            [
+                DiagnosticKind::UnboundVariable { variable: "?ship".to_string() },
+                DiagnosticKind::UnboundVariable { variable: "?ship".to_string() },
+            ]
+        );
+    }
+}